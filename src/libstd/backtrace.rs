@@ -91,14 +91,22 @@
 // `Backtrace`, but that's a relatively small price to pay relative to capturing
 // a backtrace or actually symbolizing it.
 
+use crate::borrow::Cow;
+use crate::boxed::Box;
+use crate::cell::{RefCell, UnsafeCell};
 use crate::env;
+use crate::ffi::c_void;
 use crate::fmt;
+use crate::io;
+use crate::marker::PhantomData;
+use crate::path::{Path, PathBuf};
+use crate::string::String;
 use crate::sync::atomic::{AtomicUsize, Ordering::SeqCst};
-use crate::sync::Mutex;
-use crate::sys_common::backtrace::{output_filename, lock};
+use crate::sync::Once;
+use crate::sys_common::backtrace::{self as sys_backtrace, lock, BytesOrWide};
+use crate::time::Instant;
 use crate::vec::Vec;
 use backtrace_rs as backtrace;
-use backtrace::BytesOrWideString;
 
 /// A captured OS thread stack backtrace.
 ///
@@ -129,13 +137,42 @@ pub enum BacktraceStatus {
 enum Inner {
     Unsupported,
     Disabled,
-    Captured(Mutex<Capture>),
+    Captured(LazilyResolvedCapture),
+}
+
+/// A capture which is resolved the first time it is looked at.
+struct LazilyResolvedCapture {
+    sync: Once,
+    capture: UnsafeCell<Capture>,
+    /// The number of the capture, see `Backtrace::id`.
+    id: u64,
+    /// When the stack was walked, see `Backtrace::captured_at`.
+    captured_at: Option<Instant>,
+}
+
+// The capture is only modified by the first call to `force`, which `sync`
+// keeps other threads from racing with.
+unsafe impl Sync for LazilyResolvedCapture where Capture: Sync {}
+
+impl LazilyResolvedCapture {
+    fn new(capture: Capture, id: u64, captured_at: Option<Instant>) -> LazilyResolvedCapture {
+        let capture = UnsafeCell::new(capture);
+        LazilyResolvedCapture { sync: Once::new(), capture, id, captured_at }
+    }
+
+    fn force(&self) -> &Capture {
+        self.sync.call_once(|| unsafe { (*self.capture.get()).resolve() });
+        unsafe { &*self.capture.get() }
+    }
 }
 
 struct Capture {
     actual_start: usize,
     resolved: bool,
     frames: Vec<BacktraceFrame>,
+    /// The indices of the frames at which the backtraces chained through
+    /// `Backtrace::chain` begin.
+    spawned_at: Vec<usize>,
 }
 
 fn _assert_send_sync() {
@@ -143,22 +180,24 @@ fn _assert_send_sync() {
     _assert::<Backtrace>();
 }
 
-struct BacktraceFrame {
+/// A frame of a captured `Backtrace`, as returned by `Backtrace::frames`.
+///
+/// The name, file and line of a frame are those of its innermost symbol, the
+/// function the instruction pointer is in, which may have been inlined into
+/// another one.
+#[derive(Clone)]
+pub struct BacktraceFrame {
     frame: backtrace::Frame,
     symbols: Vec<BacktraceSymbol>,
 }
 
+#[derive(Clone)]
 struct BacktraceSymbol {
     name: Option<Vec<u8>>,
     filename: Option<BytesOrWide>,
     lineno: Option<u32>,
 }
 
-enum BytesOrWide {
-    Bytes(Vec<u8>),
-    Wide(Vec<u16>),
-}
-
 impl Backtrace {
     /// Returns whether backtrace captures are enabled through environment
     /// variables.
@@ -204,7 +243,23 @@ impl Backtrace {
         if !Backtrace::enabled() {
             return Backtrace { inner: Inner::Disabled };
         }
-        Backtrace::create(Backtrace::capture as usize)
+        Backtrace::create(Backtrace::capture as usize, None)
+    }
+
+    /// Capture a stack backtrace of the current thread, keeping at most `max`
+    /// of its innermost frames.
+    ///
+    /// This function behaves the same as `capture`, including being a noop
+    /// unless enabled through environment variables, except that the stack
+    /// isn't walked any further once `max` frames were captured. This makes it
+    /// cheaper to attach backtraces to errors in hot paths when only the top
+    /// of the stack is of interest.
+    #[inline(never)] // want to make sure there's a frame here to remove
+    pub fn capture_with_depth(max: usize) -> Backtrace {
+        if !Backtrace::enabled() {
+            return Backtrace { inner: Inner::Disabled };
+        }
+        Backtrace::create(Backtrace::capture_with_depth as usize, Some(max))
     }
 
     /// Forcibly captures a full backtrace, regardless of environment variable
@@ -219,12 +274,12 @@ impl Backtrace {
     /// parts of code.
     #[inline(never)] // want to make sure there's a frame here to remove
     pub fn force_capture() -> Backtrace {
-        Backtrace::create(Backtrace::force_capture as usize)
+        Backtrace::create(Backtrace::force_capture as usize, None)
     }
 
     // Capture a backtrace which start just before the function addressed by
-    // `ip`
-    fn create(ip: usize) -> Backtrace {
+    // `ip`, stopping after `max` frames following it if given
+    fn create(ip: usize, max: Option<usize>) -> Backtrace {
         let _lock = lock();
         let mut frames = Vec::new();
         let mut actual_start = None;
@@ -234,7 +289,10 @@ impl Backtrace {
                 if frame.symbol_address() as usize == ip && actual_start.is_none() {
                     actual_start = Some(frames.len());
                 }
-                true
+                match (actual_start, max) {
+                    (Some(start), Some(max)) => frames.len() - start < max,
+                    _ => true,
+                }
             });
         }
 
@@ -244,11 +302,14 @@ impl Backtrace {
         let inner = if frames.len() == 0 {
             Inner::Unsupported
         } else {
-            Inner::Captured(Mutex::new(Capture {
+            let capture = Capture {
                 actual_start: actual_start.unwrap_or(0),
                 frames,
                 resolved: false,
-            }))
+                spawned_at: Vec::new(),
+            };
+            let id = next_capture_id();
+            Inner::Captured(LazilyResolvedCapture::new(capture, id, Some(Instant::now())))
         };
 
         Backtrace { inner }
@@ -264,61 +325,598 @@ impl Backtrace {
             Inner::Captured(_) => BacktraceStatus::Captured,
         }
     }
+
+    /// Returns the frames of this backtrace, innermost first, resolving them
+    /// if this hasn't been done yet.
+    ///
+    /// The frames of capturing the backtrace itself are left out. Backtraces
+    /// which weren't captured have no frames.
+    pub fn frames(&self) -> &[BacktraceFrame] {
+        match &self.inner {
+            Inner::Captured(c) => {
+                let capture = c.force();
+                &capture.frames[capture.actual_start..]
+            }
+            _ => &[],
+        }
+    }
+
+    /// Returns the number of frames of this backtrace, see `frames`.
+    ///
+    /// Backtraces which weren't captured, for instance because they are
+    /// disabled, have no frames.
+    pub fn len(&self) -> usize {
+        self.frames().len()
+    }
+
+    /// Returns whether this backtrace has no frames, see `len`.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns a backtrace made of the frames of this one followed by the ones
+    /// of `caller`, for instance the backtrace of where a future was spawned
+    /// following the one of where it was polled.
+    ///
+    /// The frames are resolved and copied into the returned backtrace, which
+    /// is therefore independent of both. It is displayed with a
+    /// `--- spawned at ---` line between the frames of each backtrace, which
+    /// are numbered on across it. Short backtraces are trimmed at
+    /// `__rust_begin_short_backtrace` separately for each of them. If neither
+    /// backtrace has any frames, the result is as empty as this one.
+    pub fn chain(&self, caller: &Backtrace) -> Backtrace {
+        if self.is_empty() && caller.is_empty() {
+            let inner = match self.inner {
+                Inner::Disabled => Inner::Disabled,
+                _ => Inner::Unsupported,
+            };
+            return Backtrace { inner };
+        }
+
+        let mut spawned_at = Vec::new();
+        append_spawned_at(&mut spawned_at, self, 0);
+        spawned_at.push(self.len());
+        append_spawned_at(&mut spawned_at, caller, self.len());
+        let frames = self.frames().iter().chain(caller.frames()).cloned().collect();
+        let capture = Capture { actual_start: 0, resolved: true, frames, spawned_at };
+        let id = next_capture_id();
+        let captured_at = self.captured_at().or_else(|| caller.captured_at());
+        Backtrace { inner: Inner::Captured(LazilyResolvedCapture::new(capture, id, captured_at)) }
+    }
+
+    /// Returns the number of this backtrace among the ones captured by this
+    /// process, or `None` if it wasn't captured.
+    ///
+    /// Backtraces are numbered in the order they are captured, starting at 1,
+    /// so that tools collecting many of them can order and refer to them.
+    /// Backtraces captured by different threads at the same time may be
+    /// numbered in either order though. A chained backtrace gets a number of
+    /// its own, see `chain`.
+    pub fn id(&self) -> Option<u64> {
+        match &self.inner {
+            Inner::Captured(c) => Some(c.id),
+            _ => None,
+        }
+    }
+
+    /// Returns when this backtrace was captured, or `None` if it wasn't.
+    ///
+    /// A chained backtrace was captured when the innermost of its backtraces
+    /// was, see `chain`.
+    pub fn captured_at(&self) -> Option<Instant> {
+        match &self.inner {
+            Inner::Captured(c) => c.captured_at,
+            _ => None,
+        }
+    }
+}
+
+/// Returns the number of the next captured backtrace.
+///
+/// Captures number themselves once their stack was walked, after releasing the
+/// lock. A single `Relaxed` increment keeps the numbers unique and ordered the
+/// same way as any captures ordered by other means, such as the ones of a
+/// single thread, but captures racing on different threads may be numbered in
+/// either order. Without 64-bit atomics the numbers wrap at `usize::MAX`.
+fn next_capture_id() -> u64 {
+    #[cfg(target_has_atomic = "64")]
+    {
+        use crate::sync::atomic::{AtomicU64, Ordering::Relaxed};
+
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        NEXT_ID.fetch_add(1, Relaxed)
+    }
+    #[cfg(not(target_has_atomic = "64"))]
+    {
+        use crate::sync::atomic::Ordering::Relaxed;
+
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+        NEXT_ID.fetch_add(1, Relaxed) as u64
+    }
+}
+
+/// Appends the frames at which the backtraces chained into `bt` begin to
+/// `spawned_at`, counting from `offset` on.
+fn append_spawned_at(spawned_at: &mut Vec<usize>, bt: &Backtrace, offset: usize) {
+    if let Inner::Captured(c) = &bt.inner {
+        let capture = c.force();
+        spawned_at.extend(capture.spawned_at.iter().map(|i| i - capture.actual_start + offset));
+    }
+}
+
+impl BacktraceFrame {
+    /// Returns the current instruction pointer of this frame.
+    pub fn ip(&self) -> *mut c_void {
+        self.frame.ip()
+    }
+
+    /// Returns the demangled name of the function this frame is in, if it
+    /// could be resolved.
+    pub fn symbol_name(&self) -> Option<String> {
+        let name = self.symbols.first()?.name.as_ref()?;
+        Some(format!("{:#}", backtrace::SymbolName::new(name)))
+    }
+
+    /// Returns the source file this frame is in, if debug information is
+    /// available.
+    pub fn filename(&self) -> Option<PathBuf> {
+        Some(self.symbols.first()?.filename.as_ref()?.to_path().into_owned())
+    }
+
+    /// Returns the line number this frame is at, if debug information is
+    /// available.
+    pub fn lineno(&self) -> Option<u32> {
+        self.symbols.first()?.lineno
+    }
+
+    /// Returns the column number this frame is at.
+    ///
+    /// The `backtrace` crate used by the standard library (0.3.37) doesn't
+    /// read columns from the debug information, so this always returns
+    /// `None` for now.
+    pub fn colno(&self) -> Option<u32> {
+        None
+    }
+}
+
+impl fmt::Debug for BacktraceFrame {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("BacktraceFrame")
+            .field("ip", &self.ip())
+            .field("symbol_name", &self.symbol_name())
+            .field("filename", &self.filename())
+            .field("lineno", &self.lineno())
+            .field("colno", &self.colno())
+            .finish()
+    }
+}
+
+/// The maximum number of frames printed in short backtraces, unless
+/// `RUST_BACKTRACE_LIMIT` says otherwise.
+pub use crate::sys_common::backtrace::MAX_NB_FRAMES;
+
+/// The name of the function at which short backtraces are trimmed.
+///
+/// Short backtraces leave out the frame of a function of this name and every
+/// frame after it, which belong to the runtime.
+pub use crate::sys_common::backtrace::SHORT_BACKTRACE_SENTINEL;
+
+/// Returns a fingerprint of the current thread's stack.
+///
+/// The hash is computed from the demangled names of the frames that a short
+/// panic backtrace would print, ignoring their addresses and line numbers. It
+/// is deterministic across runs of the same binary, which makes it suitable
+/// for grouping crash reports, for example from a custom panic hook.
+///
+/// On platforms where backtraces aren't supported every stack hashes to the
+/// same value.
+pub fn backtrace_hash() -> u64 {
+    sys_backtrace::hash()
+}
+
+/// Prints the current backtrace to stderr, for C code linked into a Rust
+/// program to dump the Rust side of the stack on its own error paths.
+///
+/// The backtrace is printed like the one of a panic, as configured through
+/// `RUST_BACKTRACE`, or as a short one if that doesn't enable backtraces, since
+/// the caller asked for it. It is printed under the same lock as panic
+/// backtraces. A panic while printing it is swallowed rather than unwinding
+/// into C, and so are errors writing to stderr.
+///
+/// C code declares it as `void rust_print_backtrace(void);`.
+///
+/// This takes locks and allocates, so it must not be called from a signal
+/// handler; use `print_minimal` there instead. It isn't reentrant either:
+/// called while the same thread is printing the backtrace of a panic, for
+/// instance from a callback of C code running during it, it only prints a
+/// line saying that the backtrace was skipped.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn rust_print_backtrace() {
+    sys_backtrace::print_for_ffi()
+}
+
+/// Writes the instruction pointers of the current thread's stack to `w`, one
+/// per line.
+///
+/// This is a bare-bones alternative to capturing a `Backtrace`, meant to be
+/// called from signal handlers, for example to report a `SIGSEGV`. It doesn't
+/// allocate, take locks or resolve symbols; the addresses can be symbolized
+/// offline with tools like `addr2line`. As long as `w` only issues `write`
+/// system calls, such as a wrapper around a raw file descriptor, nothing done
+/// here is unsafe to do in a signal handler on Unix platforms. On Windows the
+/// stack is walked through `dbghelp.dll`, which gives no such guarantees.
+pub fn print_minimal(w: &mut dyn io::Write) -> io::Result<()> {
+    sys_backtrace::print_minimal(w)
+}
+
+/// Writes the instruction pointers of the current thread's stack to `w` like
+/// `print_minimal`, headed by a line naming the `signal` which triggered the
+/// dump, like `Process received signal SIGSEGV (11) - backtrace follows`.
+///
+/// Looking up the name of the signal is as safe to do in a signal handler as
+/// the rest of `print_minimal`. Signals without a known name are only printed
+/// by their number.
+pub fn print_minimal_for_signal(w: &mut dyn io::Write, signal: i32) -> io::Result<()> {
+    sys_backtrace::print_minimal_for_signal(w, signal)
+}
+
+/// Why printing a backtrace through `print` failed.
+///
+/// It converts into an `io::Error`, which is the writer's own error if writing
+/// failed, and wraps the `PrintError` otherwise, so it can be recovered from
+/// the `io::Error` by downcasting.
+pub use crate::sys_common::backtrace::PrintError;
+
+/// Writes the current thread's backtrace to `w` like the panic hook does, as
+/// configured through `RUST_BACKTRACE` and the other environment variables.
+///
+/// Nothing is written if backtraces are disabled. Unlike for panics, failing
+/// to append the backtrace to the file named by `RUST_BACKTRACE_FILE` is
+/// reported, after it was written to `w`, so the caller can for instance try
+/// another sink.
+pub fn print(w: &mut dyn io::Write) -> Result<(), PrintError> {
+    sys_backtrace::try_print(w, sys_backtrace::Verbosity::Configured, false, None)
+}
+
+/// Writes the current thread's backtrace to `w` like `print`, headed by a note
+/// that frames may be missing near the top because the stack overflowed.
+///
+/// This is meant for stack overflow handlers of the program's own which run on
+/// a large enough alternate stack, as the one installed by the standard
+/// library only fits reporting the overflow. Like `print`, this allocates,
+/// takes locks and resolves symbols, so it isn't safe to call from a signal
+/// handler if the overflow may have happened while doing any of those.
+pub fn print_after_overflow(w: &mut dyn io::Write) -> io::Result<()> {
+    sys_backtrace::print_after_overflow(w, sys_backtrace::Verbosity::Configured, false)
+}
+
+/// Writes the full backtraces of all threads of the process to `w`, printing
+/// stacks shared by several threads only once.
+///
+/// This is meant for diagnosing hangs, for example from a watchdog. Each stack
+/// is headed by the ids the operating system knows its threads by, which can
+/// be matched with the ones of the panic backtrace headers.
+///
+/// Only the current thread's backtrace is printed unless enabled through
+/// `RUST_BACKTRACE_INTERRUPT_THREADS=1`, the other threads are listed without
+/// one. If enabled, they are interrupted with `SIGURG` to walk their own
+/// stacks, so a handler the program installed for it is briefly replaced, and
+/// threads blocking the signal are listed without a backtrace. Walking the
+/// stack while interrupted isn't async-signal-safe, for instance a thread
+/// interrupted while loading a library ends up deadlocked; once a thread got
+/// stuck like this, no thread is interrupted anymore.
+///
+/// # Errors
+///
+/// Fails on platforms other than Linux, where this isn't supported yet, with
+/// an error of kind `ErrorKind::Other`. Errors writing to `w` are returned as
+/// well.
+pub fn print_all_threads(w: &mut dyn io::Write) -> io::Result<()> {
+    sys_backtrace::print_all_threads(w)
+}
+
+/// Writes the full backtrace of the stack a saved machine context belongs to
+/// to `w`, like `print_all_threads` prints each stack.
+///
+/// This is meant for code which is handed the context of a fault, like a
+/// signal handler installed with `SA_SIGINFO` or a JIT's exception handler,
+/// and wants the backtrace from where the fault happened rather than from the
+/// handler.
+///
+/// This is only available on platforms with such contexts, Unix and Windows.
+/// Walking the stack from a context isn't supported on any of them yet, as
+/// `backtrace` can't start from one, so this currently always fails with an
+/// error of kind `ErrorKind::Other`.
+///
+/// # Safety
+///
+/// `ctx` must point to a valid machine context of the platform, such as a
+/// `ucontext_t` on Unix or a `CONTEXT` on Windows, whose stack is still alive.
+#[cfg(any(unix, windows))]
+pub unsafe fn print_from_context(w: &mut dyn io::Write, ctx: *mut c_void) -> io::Result<()> {
+    sys_backtrace::print_from_context(w, ctx)
+}
+
+/// Has short backtraces end before the function `sentinel` as well, besides
+/// the entry points of the standard library.
+///
+/// This lets libraries running code on behalf of their users, like test
+/// harnesses or async executors, leave their own frames out of the backtraces
+/// of panics in that code. `sentinel` is the address of a function, such as
+/// `run_task as *const c_void`, which must be marked `#[inline(never)]` to show
+/// up on the stack. Frames are compared by the start address of the function
+/// they are in, rather than by name.
+pub fn add_short_backtrace_sentinel(sentinel: *const c_void) {
+    sys_backtrace::add_short_backtrace_sentinel(sentinel as usize)
+}
+
+/// Has short backtraces of the current thread end before the function which
+/// entered it, for as long as it's alive. See `ShortBacktraceGuard::enter`.
+#[must_use = "short backtraces are only trimmed until the guard is dropped"]
+pub struct ShortBacktraceGuard {
+    depth: usize,
+    // The guard only applies to the thread which entered it.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl ShortBacktraceGuard {
+    /// Has short backtraces end before the calling function, until the
+    /// returned guard is dropped.
+    ///
+    /// This is like `add_short_backtrace_sentinel`, for code calling into
+    /// user code through a wrapper, like a test harness. The caller is
+    /// recognized by the start address of its function, which is looked up by
+    /// walking the stack once, so it must be marked `#[inline(never)]` to show
+    /// up on the stack. Guards nest, short backtraces end at the function which
+    /// entered the innermost one still alive. The registered sentinels and
+    /// `__rust_begin_short_backtrace` still end short backtraces as well.
+    #[inline(never)]
+    pub fn enter() -> ShortBacktraceGuard {
+        let caller = sys_backtrace::caller_function(ShortBacktraceGuard::enter as usize);
+        let depth = sys_backtrace::push_short_backtrace_guard(caller);
+        ShortBacktraceGuard { depth, _not_send: PhantomData }
+    }
+}
+
+impl Drop for ShortBacktraceGuard {
+    fn drop(&mut self) {
+        sys_backtrace::pop_short_backtrace_guard(self.depth);
+    }
+}
+
+impl fmt::Debug for ShortBacktraceGuard {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ShortBacktraceGuard").field("depth", &self.depth).finish()
+    }
+}
+
+/// Writes the instruction pointers of the current thread's stack into `buf`,
+/// one per line in the same format as `print_minimal`, and returns how many
+/// bytes were written.
+///
+/// Nothing is allocated, which makes this usable where the heap can't be, for
+/// example from a hook installed through `std::alloc::set_alloc_error_hook`.
+/// Symbols aren't resolved as that allocates. If `buf` is too small, the
+/// output is cut off after the last line which fits.
+pub fn print_into(buf: &mut [u8]) -> usize {
+    sys_backtrace::print_into(buf)
+}
+
+/// Returns how many frames the current thread's stack has.
+///
+/// This walks the stack without resolving any symbols, which makes it much
+/// cheaper than capturing or printing a backtrace, for example to decide
+/// whether printing one is worth it. If `trim` is set, only the frames up to
+/// the short backtrace sentinels registered through
+/// `add_short_backtrace_sentinel` are counted.
+///
+/// Walking the stack still takes the global backtrace lock and isn't
+/// async-signal-safe on every platform, so unlike `print_minimal` this must
+/// not be called from signal handlers.
+pub fn frame_count(trim: bool) -> usize {
+    sys_backtrace::frame_count(trim)
+}
+
+/// A frame of the current thread's stack, as handed out by `foreach_frame`.
+#[derive(Debug)]
+pub struct Frame<'a> {
+    ip: *mut c_void,
+    symbol_name: Option<&'a str>,
+    filename: Option<&'a Path>,
+    lineno: Option<u32>,
+}
+
+impl<'a> Frame<'a> {
+    /// Returns the current instruction pointer of this frame.
+    pub fn ip(&self) -> *mut c_void {
+        self.ip
+    }
+
+    /// Returns the demangled name of the function this frame is in, if it
+    /// could be resolved.
+    pub fn symbol_name(&self) -> Option<&'a str> {
+        self.symbol_name
+    }
+
+    /// Returns the source file this frame is in, if debug information is
+    /// available.
+    pub fn filename(&self) -> Option<&'a Path> {
+        self.filename
+    }
+
+    /// Returns the line number this frame is at, if debug information is
+    /// available.
+    pub fn lineno(&self) -> Option<u32> {
+        self.lineno
+    }
+}
+
+/// What `foreach_frame` does after handing out a frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameControl {
+    /// Hands out the next frame.
+    Continue,
+    /// Passes over the rest of the functions inlined into the frame, and hands
+    /// out the next frame after them.
+    Skip,
+    /// Stops walking the stack right away, without resolving any more frames.
+    Stop,
+}
+
+/// Walks the current thread's stack and calls `f` with each of its frames,
+/// starting with the innermost one.
+///
+/// If `trim` is set, only the frames a short panic backtrace would print are
+/// handed out, otherwise every frame is. A frame is handed out once per
+/// function inlined into it, with the same instruction pointer.
+///
+/// What `f` returns decides whether to go on. Returning `FrameControl::Stop`
+/// halts the underlying stack walk immediately, so searching for a frame near
+/// the top of a deep stack doesn't pay for resolving the rest of it.
+///
+/// The global backtrace lock is held while `f` runs, so `f` must not capture
+/// or print a backtrace itself, this would deadlock.
+pub fn foreach_frame<F: FnMut(Frame<'_>) -> FrameControl>(trim: bool, mut f: F) {
+    sys_backtrace::foreach_frame(trim, &mut |ip, symbol_name, filename, lineno| {
+        f(Frame { ip, symbol_name, filename, lineno })
+    })
+}
+
+type FrameFormatter = dyn Fn(&mut fmt::Formatter<'_>, Frame<'_>) -> fmt::Result;
+
+thread_local! {
+    static FRAME_FORMATTER: RefCell<Option<Box<FrameFormatter>>> = RefCell::new(None)
+}
+
+/// Installs `f` to format the frames of the backtraces printed by the current
+/// thread, replacing the previous one.
+///
+/// Panic backtraces as well as captured ones call `f` once per symbol in place
+/// of printing the usual index, address, name and location of the frame, while
+/// walking and trimming the stack stays the same. `f` should end its output
+/// with a newline.
+///
+/// `f` must not install or take a formatter itself, this would panic.
+pub fn set_frame_formatter<F>(f: F)
+where
+    F: Fn(&mut fmt::Formatter<'_>, Frame<'_>) -> fmt::Result + 'static,
+{
+    FRAME_FORMATTER.with(|formatter| *formatter.borrow_mut() = Some(Box::new(f)));
+}
+
+/// Removes the frame formatter installed on the current thread, returning it.
+///
+/// Backtraces are printed as usual afterwards. See `set_frame_formatter`.
+pub fn take_frame_formatter() -> Option<Box<FrameFormatter>> {
+    FRAME_FORMATTER.with(|formatter| formatter.borrow_mut().take())
+}
+
+/// Formats a frame with the formatter installed on the current thread, or
+/// returns `None` if there is none.
+#[cfg(not(feature = "backtrace-minimal"))]
+pub(crate) fn format_frame(
+    fmt: &mut fmt::Formatter<'_>,
+    ip: *mut c_void,
+    symbol_name: Option<&str>,
+    filename: Option<&Path>,
+    lineno: Option<u32>,
+) -> Option<fmt::Result> {
+    FRAME_FORMATTER
+        .try_with(|formatter| {
+            let formatter = formatter.try_borrow().ok()?;
+            let f = formatter.as_ref()?;
+            Some(f(fmt, Frame { ip, symbol_name, filename, lineno }))
+        })
+        .ok()?
+}
+
+type LineHook = dyn FnMut(&str) -> Cow<'_, str>;
+
+thread_local! {
+    static LINE_HOOK: RefCell<Option<Box<LineHook>>> = RefCell::new(None)
+}
+
+/// Installs `f` to rewrite each line of the backtraces printed by the current
+/// thread, replacing the previous one.
+///
+/// Every line printed for a panic, in whichever layout, is handed to `f`
+/// without its newline and replaced with what `f` returns, for instance to
+/// wrap it in the envelope of a logging framework. The lines of backtraces
+/// printed by `f` itself are printed as they are.
+///
+/// `f` must not panic while the thread is panicking, which is when most
+/// backtraces are printed: like any other panic during a panic, that aborts
+/// the process. The line is only printed as it is if `f` panics while printing
+/// a backtrace otherwise, for instance through `print`.
+pub fn set_line_hook<F>(f: F)
+where
+    F: FnMut(&str) -> Cow<'_, str> + 'static,
+{
+    LINE_HOOK.with(|hook| *hook.borrow_mut() = Some(Box::new(f)));
+}
+
+/// Removes the line hook installed on the current thread, returning it.
+///
+/// Backtraces are printed as usual afterwards. See `set_line_hook`.
+pub fn take_line_hook() -> Option<Box<LineHook>> {
+    LINE_HOOK.with(|hook| hook.borrow_mut().take())
+}
+
+/// Returns whether a line hook is installed on the current thread.
+#[cfg(not(feature = "backtrace-minimal"))]
+pub(crate) fn line_hook_installed() -> bool {
+    LINE_HOOK
+        .try_with(|hook| hook.try_borrow().map_or(true, |hook| hook.is_some()))
+        .unwrap_or(false)
+}
+
+/// Rewrites `line` with the line hook installed on the current thread, or
+/// returns `None` if there is none or it panicked. A panic of the hook while
+/// the thread is already panicking aborts instead, see `set_line_hook`.
+#[cfg(not(feature = "backtrace-minimal"))]
+pub(crate) fn hook_line(line: &str) -> Option<String> {
+    use crate::panic::{self, AssertUnwindSafe};
+
+    LINE_HOOK
+        .try_with(|hook| {
+            // A backtrace printed by the hook itself finds it borrowed.
+            let mut hook = hook.try_borrow_mut().ok()?;
+            let f = hook.as_mut()?;
+            panic::catch_unwind(AssertUnwindSafe(|| f(line).into_owned())).ok()
+        })
+        .ok()
+        .and_then(|line| line)
 }
 
 impl fmt::Display for Backtrace {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, fmt)
+        match self.inner {
+            Inner::Disabled => fmt.write_str("<backtrace disabled>"),
+            _ => fmt::Debug::fmt(self, fmt),
+        }
     }
 }
 
 impl fmt::Debug for Backtrace {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut capture = match &self.inner {
+        let capture = match &self.inner {
             Inner::Unsupported => return fmt.write_str("unsupported backtrace"),
             Inner::Disabled => return fmt.write_str("disabled backtrace"),
-            Inner::Captured(c) => c.lock().unwrap(),
+            Inner::Captured(c) => c.force(),
         };
-        capture.resolve();
 
-        let full = fmt.alternate();
-        let (frames, style) = if full {
-            (&capture.frames[..], backtrace::PrintFmt::Full)
+        // Captured backtraces are printed the same way as the ones printed
+        // when panicking, so they are trimmed and filtered the same way too.
+        let style = if fmt.alternate() {
+            backtrace::PrintFmt::Full
         } else {
-            (&capture.frames[capture.actual_start..], backtrace::PrintFmt::Short)
-        };
-
-        // When printing paths we try to strip the cwd if it exists, otherwise
-        // we just print the path as-is. Note that we also only do this for the
-        // short format, because if it's full we presumably want to print
-        // everything.
-        let cwd = crate::env::current_dir();
-        let mut print_path = move |fmt: &mut fmt::Formatter<'_>, path: BytesOrWideString<'_>| {
-            output_filename(fmt, path, style, cwd.as_ref().ok())
+            backtrace::PrintFmt::Short
         };
-
-        let mut f = backtrace::BacktraceFmt::new(fmt, style, &mut print_path);
-        f.add_context()?;
-        for frame in frames {
-            let mut f = f.frame();
-            if frame.symbols.is_empty() {
-                f.print_raw(frame.frame.ip(), None, None, None)?;
-            } else {
-                for symbol in frame.symbols.iter() {
-                    f.print_raw(
-                        frame.frame.ip(),
-                        symbol.name.as_ref().map(|b| backtrace::SymbolName::new(b)),
-                        symbol.filename.as_ref().map(|b| match b {
-                            BytesOrWide::Bytes(w) => BytesOrWideString::Bytes(w),
-                            BytesOrWide::Wide(w) => BytesOrWideString::Wide(w),
-                        }),
-                        symbol.lineno,
-                    )?;
-                }
-            }
-        }
-        f.finish()?;
-        Ok(())
+        let start = if style == backtrace::PrintFmt::Full { 0 } else { capture.actual_start };
+        let ips = capture.frames[start..].iter().map(|f| f.frame.ip() as usize).collect();
+        let spawned_at = capture.spawned_at.iter().map(|i| i - start).collect();
+        let _lock = lock();
+        unsafe { sys_backtrace::fmt_captured(fmt, ips, spawned_at, style) }
     }
 }
 
@@ -340,10 +938,7 @@ impl Capture {
                 backtrace::resolve_frame_unsynchronized(&frame.frame, |symbol| {
                     symbols.push(BacktraceSymbol {
                         name: symbol.name().map(|m| m.as_bytes().to_vec()),
-                        filename: symbol.filename_raw().map(|b| match b {
-                            BytesOrWideString::Bytes(b) => BytesOrWide::Bytes(b.to_owned()),
-                            BytesOrWideString::Wide(b) => BytesOrWide::Wide(b.to_owned()),
-                        }),
+                        filename: symbol.filename_raw().map(BytesOrWide::new),
                         lineno: symbol.lineno(),
                     });
                 });
@@ -426,3 +426,60 @@ pub fn catch_unwind<F: FnOnce() -> R + UnwindSafe, R>(f: F) -> Result<R> {
 pub fn resume_unwind(payload: Box<dyn Any + Send>) -> ! {
     panicking::update_count_then_panic(payload)
 }
+
+/// The verbosity of the backtraces printed by the default panic hook.
+///
+/// See [`backtrace_style`] for how it is determined.
+///
+/// [`backtrace_style`]: ./fn.backtrace_style.html
+#[unstable(feature = "panic_backtrace_style", issue = "0")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BacktraceStyle {
+    /// No backtrace is printed.
+    Disabled,
+    /// A backtrace trimmed to the frames relevant to the panic is printed.
+    Short,
+    /// A backtrace of every frame, including their addresses, is printed.
+    Full,
+}
+
+/// Returns how the default panic hook prints backtraces.
+///
+/// This is configured through the `RUST_BACKTRACE` environment variable:
+///
+/// * `0` or an empty value disables backtraces, as does leaving it unset,
+///   except on Fuchsia where that prints full backtraces.
+/// * `full`, `full-no-std` and `symbols` print full backtraces, the latter
+///   two only changing how their frames are picked and printed.
+/// * Any other value, including `1`, `short`, `compact` and `addrs`, prints
+///   short backtraces.
+///
+/// Backtraces are always disabled if the standard library was built without
+/// support for them. The environment is only read once, so this is cheap to
+/// call repeatedly, for example to avoid capturing a backtrace in a custom
+/// panic hook when they are disabled.
+///
+/// Note that a panic while panicking always prints a full backtrace,
+/// regardless of the value returned here.
+///
+/// # Examples
+///
+/// ```
+/// #![feature(panic_backtrace_style)]
+/// use std::panic::{self, BacktraceStyle};
+///
+/// if panic::backtrace_style() != BacktraceStyle::Disabled {
+///     println!("panics will print a backtrace");
+/// }
+/// ```
+#[unstable(feature = "panic_backtrace_style", issue = "0")]
+pub fn backtrace_style() -> BacktraceStyle {
+    if !cfg!(feature = "backtrace") {
+        return BacktraceStyle::Disabled;
+    }
+    match crate::sys_common::backtrace::log_enabled() {
+        None => BacktraceStyle::Disabled,
+        Some(backtrace_rs::PrintFmt::Short) => BacktraceStyle::Short,
+        Some(_) => BacktraceStyle::Full,
+    }
+}
@@ -15,7 +15,7 @@ use crate::intrinsics;
 use crate::mem;
 use crate::ptr;
 use crate::raw;
-use crate::sys::stdio::panic_output;
+use crate::sys::stdio::{panic_output, panic_output_is_terminal};
 use crate::sys_common::rwlock::RWLock;
 use crate::sys_common::{thread_info, util, backtrace};
 use crate::thread;
@@ -162,9 +162,9 @@ fn default_hook(info: &PanicInfo<'_>) {
         let panics = update_panic_count(0);
 
         if panics >= 2 {
-            Some(backtrace_rs::PrintFmt::Full)
+            Some(backtrace::Verbosity::Forced(backtrace_rs::PrintFmt::Full))
         } else {
-            backtrace::log_enabled()
+            backtrace::log_enabled().map(|_| backtrace::Verbosity::Configured)
         }
     } else {
         None
@@ -183,7 +183,18 @@ fn default_hook(info: &PanicInfo<'_>) {
     let thread = thread_info::current_thread();
     let name = thread.as_ref().and_then(|t| t.name()).unwrap_or("<unnamed>");
 
-    let write = |err: &mut dyn crate::io::Write| {
+    let write = |err: &mut dyn crate::io::Write, is_terminal: bool| {
+        // The message is printed along with the backtrace if there is one, to
+        // keep them together.
+        if let Some(verbosity) = log_backtrace {
+            let _ = backtrace::print_with_message(
+                err,
+                verbosity,
+                is_terminal,
+                Some(format_args!("thread '{}' panicked at '{}', {}", name, msg, location)),
+            );
+            return;
+        }
         let _ = writeln!(err, "thread '{}' panicked at '{}', {}",
                          name, msg, location);
 
@@ -192,11 +203,10 @@ fn default_hook(info: &PanicInfo<'_>) {
 
             static FIRST_PANIC: AtomicBool = AtomicBool::new(true);
 
-            if let Some(format) = log_backtrace {
-                let _ = backtrace::print(err, format);
-            } else if FIRST_PANIC.compare_and_swap(true, false, Ordering::SeqCst) {
-                let _ = writeln!(err, "note: run with `RUST_BACKTRACE=1` \
-                                       environment variable to display a backtrace.");
+            if FIRST_PANIC.compare_and_swap(true, false, Ordering::SeqCst) {
+                let _ = writeln!(err, "note: run with `{}=1` \
+                                       environment variable to display a backtrace.",
+                                 backtrace::BACKTRACE_VAR);
             }
         }
     };
@@ -204,10 +214,10 @@ fn default_hook(info: &PanicInfo<'_>) {
     if let Some(mut local) = set_panic(None) {
         // NB. In `cfg(test)` this uses the forwarding impl
         // for `Box<dyn (::realstd::io::Write) + Send>`.
-        write(&mut local);
+        write(&mut local, false);
         set_panic(Some(local));
     } else if let Some(mut out) = panic_output() {
-        write(&mut out);
+        write(&mut out, panic_output_is_terminal());
     }
 }
 
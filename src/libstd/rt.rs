@@ -29,6 +29,9 @@ fn lang_start_internal(main: &(dyn Fn() -> i32 + Sync + crate::panic::RefUnwindS
 
     sys::init();
 
+    #[cfg(feature = "backtrace")]
+    sys_common::backtrace::init_start_time();
+
     unsafe {
         let main_guard = sys::thread::guard::init();
         sys::stack_overflow::init();
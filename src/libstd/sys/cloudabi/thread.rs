@@ -57,6 +57,11 @@ impl Thread {
         // CloudABI has no way to set a thread name.
     }
 
+    pub fn os_id() -> Option<u64> {
+        // CloudABI doesn't expose the id of threads.
+        None
+    }
+
     pub fn sleep(dur: Duration) {
         let timeout = checked_dur2intervals(&dur)
             .expect("overflow converting duration to nanoseconds");
@@ -67,6 +67,10 @@ pub fn panic_output() -> Option<impl io::Write> {
     super::abi::panic::SgxPanicOutput::new()
 }
 
+pub fn panic_output_is_terminal() -> bool {
+    false
+}
+
 // This function is needed by libunwind. The symbol is named in pre-link args
 // for the target specification, so keep that in sync.
 #[cfg(not(test))]
@@ -75,6 +75,11 @@ impl Thread {
         // FIXME: could store this pointer in TLS somewhere
     }
 
+    pub fn os_id() -> Option<u64> {
+        // FIXME: the enclave has no thread ids meaningful outside of it
+        None
+    }
+
     pub fn sleep(_dur: Duration) {
         rtabort!("can't sleep"); // FIXME
     }
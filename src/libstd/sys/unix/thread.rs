@@ -150,6 +150,20 @@ impl Thread {
         // FIXME: determine whether Fuchsia has a way to set a thread name.
     }
 
+    #[cfg(any(target_os = "linux",
+              target_os = "android"))]
+    pub fn os_id() -> Option<u64> {
+        // glibc has no wrapper for gettid, so we use the syscall directly.
+        Some(unsafe { libc::syscall(libc::SYS_gettid) } as u64)
+    }
+
+    #[cfg(not(any(target_os = "linux",
+                  target_os = "android")))]
+    pub fn os_id() -> Option<u64> {
+        // FIXME: other platforms have their own ways to get a thread id.
+        None
+    }
+
     pub fn sleep(dur: Duration) {
         let mut secs = dur.as_secs();
         let mut nsecs = dur.subsec_nanos() as _;
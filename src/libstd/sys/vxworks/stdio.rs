@@ -61,3 +61,7 @@ pub const STDIN_BUF_SIZE: usize = crate::sys_common::io::DEFAULT_BUF_SIZE;
 pub fn panic_output() -> Option<impl io::Write> {
     Stderr::new().ok()
 }
+
+pub fn panic_output_is_terminal() -> bool {
+    false
+}
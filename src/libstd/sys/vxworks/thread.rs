@@ -80,6 +80,11 @@ impl Thread {
         // VxWorks does not provide a way to set the task name except at creation time
     }
 
+    pub fn os_id() -> Option<u64> {
+        // FIXME: could use the id of the current task
+        None
+    }
+
     pub fn sleep(dur: Duration) {
         let mut secs = dur.as_secs();
         let mut nsecs = dur.subsec_nanos() as _;
@@ -80,3 +80,7 @@ pub fn is_ebadf(err: &io::Error) -> bool {
 pub fn panic_output() -> Option<impl io::Write> {
     Stderr::new().ok()
 }
+
+pub fn panic_output_is_terminal() -> bool {
+    false
+}
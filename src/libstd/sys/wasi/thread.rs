@@ -27,6 +27,11 @@ impl Thread {
         // nope
     }
 
+    pub fn os_id() -> Option<u64> {
+        // nope
+        None
+    }
+
     pub fn sleep(dur: Duration) {
         let nanos = dur.as_nanos();
         assert!(nanos <= u64::max_value() as u128);
@@ -57,3 +57,7 @@ pub fn is_ebadf(_err: &io::Error) -> bool {
 pub fn panic_output() -> Option<Vec<u8>> {
     None
 }
+
+pub fn panic_output_is_terminal() -> bool {
+    false
+}
@@ -23,6 +23,11 @@ impl Thread {
         // nope
     }
 
+    pub fn os_id() -> Option<u64> {
+        // nope
+        None
+    }
+
     #[cfg(not(target_feature = "atomics"))]
     pub fn sleep(_dur: Duration) {
         panic!("can't sleep");
@@ -156,6 +156,8 @@ pub const STD_INPUT_HANDLE: DWORD = -10i32 as DWORD;
 pub const STD_OUTPUT_HANDLE: DWORD = -11i32 as DWORD;
 pub const STD_ERROR_HANDLE: DWORD = -12i32 as DWORD;
 
+pub const ENABLE_VIRTUAL_TERMINAL_PROCESSING: DWORD = 0x0004;
+
 pub const PROGRESS_CONTINUE: DWORD = 0;
 
 pub const ERROR_FILE_NOT_FOUND: DWORD = 2;
@@ -799,6 +801,7 @@ extern "system" {
     pub fn WaitForSingleObject(hHandle: HANDLE,
                                dwMilliseconds: DWORD) -> DWORD;
     pub fn SwitchToThread() -> BOOL;
+    pub fn GetCurrentThreadId() -> DWORD;
     pub fn Sleep(dwMilliseconds: DWORD);
     pub fn GetProcessId(handle: HANDLE) -> DWORD;
     pub fn CopyFileExW(lpExistingFileName: LPCWSTR,
@@ -281,3 +281,17 @@ pub fn is_ebadf(err: &io::Error) -> bool {
 pub fn panic_output() -> Option<impl io::Write> {
     Stderr::new().ok()
 }
+
+pub fn panic_output_is_terminal() -> bool {
+    // Only report a terminal if the console interprets ANSI escape sequences,
+    // which older versions of Windows don't do.
+    let handle = match get_handle(c::STD_ERROR_HANDLE) {
+        Ok(handle) => handle,
+        Err(_) => return false,
+    };
+    let mut mode = 0;
+    unsafe {
+        c::GetConsoleMode(handle, &mut mode) != 0 &&
+            mode & c::ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0
+    }
+}
@@ -72,6 +72,10 @@ impl Thread {
         unsafe { c::SwitchToThread(); }
     }
 
+    pub fn os_id() -> Option<u64> {
+        Some(unsafe { c::GetCurrentThreadId() } as u64)
+    }
+
     pub fn sleep(dur: Duration) {
         unsafe {
             c::Sleep(super::dur2timeout(dur))
@@ -2,205 +2,5142 @@
 /// supported platforms.
 
 use crate::env;
+use crate::ffi::c_void;
 use crate::fmt;
 use crate::io;
 use crate::borrow::Cow;
 use crate::io::prelude::*;
-use crate::path::{self, Path, PathBuf};
+use crate::mem;
+#[cfg(not(feature = "backtrace-minimal"))]
+use crate::path;
+use crate::path::{Path, PathBuf};
+use crate::backtrace::FrameControl;
+use crate::sync::atomic::{AtomicUsize, Ordering::SeqCst};
 use crate::sys::mutex::Mutex;
+#[cfg(not(feature = "backtrace-minimal"))]
+use crate::sys_common::thread_info;
+use crate::time::{Duration, Instant};
 
-use backtrace_rs::{BacktraceFmt, BytesOrWideString, PrintFmt};
+use backtrace_rs::{BytesOrWideString, PrintFmt, SymbolName};
+#[cfg(not(feature = "backtrace-minimal"))]
+use backtrace_rs::BacktraceFmt;
 
-/// Max number of frames to print.
-const MAX_NB_FRAMES: usize = 100;
+/// Max number of frames to print in short mode, unless overridden through
+/// `RUST_BACKTRACE_LIMIT`.
+pub const MAX_NB_FRAMES: usize = 100;
 
-pub fn lock() -> impl Drop {
-    struct Guard;
-    static LOCK: Mutex = Mutex::new();
+/// Max number of frames to print in full mode, unless overridden through
+/// `RUST_BACKTRACE_FULL_LIMIT`.
+const MAX_NB_FRAMES_FULL: usize = 10000;
 
-    impl Drop for Guard {
-        fn drop(&mut self) {
-            unsafe {
-                LOCK.unlock();
-            }
+/// Name of the environment variable enabling backtraces, see `log_enabled`.
+/// Notes telling how to get more of a backtrace refer to it by this name.
+pub const BACKTRACE_VAR: &str = "RUST_BACKTRACE";
+
+/// Name of the function short backtraces are trimmed at, its frame and the
+/// ones after it belong to the runtime.
+pub const SHORT_BACKTRACE_SENTINEL: &str = "__rust_begin_short_backtrace";
+
+/// Number of frames after which frames aren't indented any further with
+/// `RUST_BACKTRACE_TREE=1`, so deep stacks don't run off the screen.
+#[cfg(not(feature = "backtrace-minimal"))]
+const MAX_TREE_DEPTH: usize = 20;
+
+/// Instruction pointers below this address don't point into any code, see
+/// `is_corrupt_frame`.
+#[cfg(not(feature = "backtrace-minimal"))]
+const MIN_CODE_ADDR: usize = 4096;
+
+/// Width of a formatted instruction pointer, including the `0x` prefix.
+const HEX_WIDTH: usize = 2 + 2 * mem::size_of::<usize>();
+
+// ANSI escape sequences used for colored backtraces.
+#[cfg(not(feature = "backtrace-minimal"))]
+const DIM: &str = "\x1b[2m";
+#[cfg(not(feature = "backtrace-minimal"))]
+const BOLD: &str = "\x1b[1m";
+#[cfg(not(feature = "backtrace-minimal"))]
+const CYAN: &str = "\x1b[36m";
+#[cfg(not(feature = "backtrace-minimal"))]
+const YELLOW: &str = "\x1b[33m";
+#[cfg(not(feature = "backtrace-minimal"))]
+const RESET: &str = "\x1b[0m";
+
+/// How long `print` waits for its turn and the lock before going ahead
+/// without them, see `print_current`.
+const PRINT_LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How much of a backtrace `print` holds back before writing it out.
+const PRINT_BUFFER_SIZE: usize = 8 * 1024;
+
+static LOCK: Mutex = Mutex::new();
+
+struct Guard;
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        unsafe {
+            LOCK.unlock();
         }
     }
+}
 
+/// Takes the lock serializing the walks of the stack, blocking until it is
+/// free, see `try_lock` for not blocking.
+pub fn lock() -> impl Drop {
     unsafe {
         LOCK.lock();
-        return Guard;
+        Guard
+    }
+}
+
+/// Takes the lock if it is free, without blocking.
+pub fn try_lock() -> Option<impl Drop> {
+    unsafe { if LOCK.try_lock() { Some(Guard) } else { None } }
+}
+
+/// Takes the lock like `lock`, but gives up at `deadline`.
+fn lock_until(deadline: Instant) -> Option<impl Drop> {
+    loop {
+        if let Some(guard) = try_lock() {
+            return Some(guard);
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+        crate::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+/// The ticket handed to the next thread which wants to print a backtrace.
+static NEXT_TURN: AtomicUsize = AtomicUsize::new(0);
+/// The ticket of the thread whose turn it is to print a backtrace.
+static CURRENT_TURN: AtomicUsize = AtomicUsize::new(0);
+
+/// A thread's turn to print a backtrace, which passes to the next thread in
+/// line once dropped.
+struct Turn {
+    ticket: usize,
+}
+
+impl Turn {
+    /// Returns whether the threads in line before this one are done.
+    fn is_current(&self) -> bool {
+        CURRENT_TURN.load(SeqCst) >= self.ticket
     }
 }
 
+impl Drop for Turn {
+    fn drop(&mut self) {
+        // A thread which gave up waiting for its turn may finish after the
+        // threads behind it, so the turn only ever moves forward.
+        let mut current = CURRENT_TURN.load(SeqCst);
+        while current <= self.ticket {
+            match CURRENT_TURN.compare_exchange(current, self.ticket + 1, SeqCst, SeqCst) {
+                Ok(_) => break,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+/// Waits until the threads which wanted to print a backtrace before this one
+/// are done, so that concurrent backtraces are printed in the order they were
+/// asked for rather than in whichever order the lock happens to be taken.
+///
+/// Like taking the lock, this gives up at `deadline`, in which case the turn
+/// is taken anyway.
+fn wait_turn(deadline: Instant) -> Turn {
+    let turn = Turn { ticket: NEXT_TURN.fetch_add(1, SeqCst) };
+    while !turn.is_current() && Instant::now() < deadline {
+        crate::thread::sleep(Duration::from_millis(1));
+    }
+    turn
+}
+
+/// How detailed a backtrace printed by `print` is.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Verbosity {
+    /// As configured through `RUST_BACKTRACE`, see `log_enabled`. Nothing is
+    /// printed if backtraces are disabled.
+    Configured,
+    /// In the given format, regardless of `RUST_BACKTRACE`, for backtraces
+    /// which should always be printed with the same amount of detail.
+    Forced(PrintFmt),
+}
+
 /// Prints the current backtrace.
-pub fn print(w: &mut dyn Write, format: PrintFmt) -> io::Result<()> {
+///
+/// The frames are laid out as configured through `RUST_BACKTRACE_FORMAT`, see
+/// `output_style`, unless the `verbosity` is forced, which always prints
+/// them as text. `is_terminal` tells whether `w` is connected to a terminal,
+/// which decides whether the output is colored unless overridden through
+/// `RUST_BACKTRACE_COLOR`.
+///
+/// If `RUST_BACKTRACE_FILE` is set, the output is also appended to the file it
+/// names, without colors. Failing to do so is silently ignored.
+pub fn print(w: &mut dyn Write, verbosity: Verbosity, is_terminal: bool) -> io::Result<()> {
+    print_with_message(w, verbosity, is_terminal, None)
+}
+
+/// Prints the current backtrace like `print`, preceded by the `message` line
+/// if given, such as the message of the panic the backtrace is printed for.
+///
+/// The message is written out together with the backtrace, so that other
+/// output can't get in between them. It is printed even if the backtrace
+/// isn't.
+pub fn print_with_message(
+    w: &mut dyn Write,
+    verbosity: Verbosity,
+    is_terminal: bool,
+    message: Option<fmt::Arguments<'_>>,
+) -> io::Result<()> {
+    match try_print(w, verbosity, is_terminal, message) {
+        Err(PrintError::File(_)) => Ok(()),
+        res => res.map_err(io::Error::from),
+    }
+}
+
+/// Why printing a backtrace failed, see `try_print`.
+#[derive(Debug)]
+pub enum PrintError {
+    /// Writing out the backtrace failed.
+    Write(io::Error),
+    /// Formatting the frames of the backtrace failed, while the writer didn't
+    /// report any error.
+    Symbolize,
+    /// The backtrace was printed, but couldn't be appended to the file named
+    /// by `RUST_BACKTRACE_FILE`.
+    File(io::Error),
+}
+
+impl fmt::Display for PrintError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrintError::Write(err) => write!(fmt, "failed to write backtrace: {}", err),
+            PrintError::Symbolize => fmt.write_str("failed to format backtrace"),
+            PrintError::File(err) => write!(fmt, "failed to append backtrace to file: {}", err),
+        }
+    }
+}
+
+impl crate::error::Error for PrintError {
+    fn source(&self) -> Option<&(dyn crate::error::Error + 'static)> {
+        match self {
+            PrintError::Write(err) | PrintError::File(err) => Some(err),
+            PrintError::Symbolize => None,
+        }
+    }
+}
+
+/// Errors of the writer are passed on as they are, like `print` always did.
+/// The other ones are wrapped, to be told apart by downcasting the error.
+impl From<PrintError> for io::Error {
+    fn from(err: PrintError) -> io::Error {
+        match err {
+            PrintError::Write(err) => err,
+            err => io::Error::new(io::ErrorKind::Other, err),
+        }
+    }
+}
+
+/// Prints the current backtrace like `print_with_message`, telling apart how
+/// printing it failed.
+///
+/// Unlike with `print`, failing to append the backtrace to the file named by
+/// `RUST_BACKTRACE_FILE` is reported, once it was printed to `w`.
+pub fn try_print(
+    w: &mut dyn Write,
+    verbosity: Verbosity,
+    is_terminal: bool,
+    message: Option<fmt::Arguments<'_>>,
+) -> Result<(), PrintError> {
+    print_current(w, verbosity, is_terminal, message, false)
+}
+
+/// Prints the current backtrace like `print`, headed by a note that it may be
+/// incomplete, see `std::backtrace::print_after_overflow`.
+///
+/// The frames innermost to the overflow may be missing, since the stack guard
+/// page was hit while the stack was walked from there, so users shouldn't be
+/// surprised by not finding the function which overflowed at the top.
+pub fn print_after_overflow(
+    w: &mut dyn Write,
+    verbosity: Verbosity,
+    is_terminal: bool,
+) -> io::Result<()> {
+    match print_current(w, verbosity, is_terminal, None, true) {
+        Err(PrintError::File(_)) => Ok(()),
+        res => res.map_err(io::Error::from),
+    }
+}
+
+/// Prints the current backtrace, see `try_print`. `overflowed` tells whether
+/// it is printed because the stack overflowed.
+fn print_current(
+    w: &mut dyn Write,
+    verbosity: Verbosity,
+    is_terminal: bool,
+    message: Option<fmt::Arguments<'_>>,
+    overflowed: bool,
+) -> Result<(), PrintError> {
     // There are issues currently linking libbacktrace into tests, and in
     // general during libstd's own unit tests we're not testing this path. In
     // test mode immediately return here to optimize away any references to the
     // libbacktrace symbols
     if cfg!(test) {
-        return Ok(());
+        return print_message(w, message).map_err(PrintError::Write);
     }
 
+    // `RUST_BACKTRACE=compact` and the like also pick the layout, which is
+    // only what the caller asked for if it left the choice to `RUST_BACKTRACE`.
+    let (format, style) = match verbosity {
+        Verbosity::Configured => match log_enabled() {
+            Some(format) => (format, output_style()),
+            None => return print_message(w, message).map_err(PrintError::Write),
+        },
+        Verbosity::Forced(format) => (format, OutputStyle::Text),
+    };
+
+    // A panic while printing a backtrace calls the panic hook again, which
+    // would end up here and wait for the lock held by the outer call. If the
+    // outer call was printing for a panic, the process aborts once the hook
+    // returns, so all that's left to do is to skip the backtrace; otherwise
+    // the panic unwinds out of the outer call as usual.
+    let _printing = match enter_print() {
+        Some(guard) => guard,
+        None => {
+            print_message(w, message).map_err(PrintError::Write)?;
+            return writeln!(w, "panicked while printing a backtrace, skipping it")
+                .map_err(PrintError::Write);
+        }
+    };
+
     // Use a lock to prevent mixed output in multithreading context.
     // Some platforms also requires it, like `SymFromAddr` on Windows.
+    //
+    // The lock alone isn't fair, a thread polling for it may keep losing it to
+    // others, so threads first wait for their turn.
+    //
+    // A thread which never releases the lock, for instance because a signal
+    // handler printing a backtrace interrupted it, would hang every panicking
+    // thread after it, so both waits share a timeout after which the backtrace
+    // is printed without the lock. That risks garbled output and, on platforms
+    // like Windows, racing with the other thread in `SymFromAddr`; not hanging
+    // matters more in these pathological cases.
+    let deadline = Instant::now() + PRINT_LOCK_TIMEOUT;
+    let _turn = wait_turn(deadline);
+    let lock = lock_until(deadline);
     unsafe {
-        let _lock = lock();
-        _print(w, format)
+        // The stack is only walked and resolved once, the output is copied to
+        // the file once it was written out.
+        let file = env::var_os("RUST_BACKTRACE_FILE");
+        let mut w = BufferedPrint::new(w, env_flag(Flag::FlushEach));
+        if file.is_some() {
+            w.copy = Some(Vec::new());
+        }
+        print_message(&mut w, message).map_err(PrintError::Write)?;
+        if lock.is_none() {
+            let note = "note: timed out waiting for another thread to print its backtrace, \
+                        the output may be interleaved";
+            writeln!(w, "{}", note).map_err(PrintError::Write)?;
+        }
+        let color = color_enabled(is_terminal);
+        let source = FrameSource::Current;
+        let res = _print(&mut w, &source, format, style, color, overflowed);
+        let failed = w.failed;
+        let copy = w.copy.take();
+        // What was printed before formatting failed is still written out.
+        let finished = w.finish();
+        if let Err(err) = res {
+            // Without an error of the writer to pass on, formatting itself
+            // failed.
+            return Err(if failed { PrintError::Write(err) } else { PrintError::Symbolize });
+        }
+        finished.map_err(PrintError::Write)?;
+        match (file, copy) {
+            (Some(path), Some(copy)) => append_to_file(Path::new(&path), &copy),
+            _ => Ok(()),
+        }
+        .map_err(PrintError::File)
     }
 }
 
-unsafe fn _print(w: &mut dyn Write, format: PrintFmt) -> io::Result<()> {
-    struct DisplayBacktrace {
-        format: PrintFmt,
+fn print_message(w: &mut dyn Write, message: Option<fmt::Arguments<'_>>) -> io::Result<()> {
+    match message {
+        Some(message) => writeln!(w, "{}", message),
+        None => Ok(()),
     }
-    impl fmt::Display for DisplayBacktrace {
-        fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-            unsafe {
-                _print_fmt(fmt, self.format)
-            }
+}
+
+/// Prints the current backtrace to stderr, for `rust_print_backtrace` in
+/// `std::backtrace` to call on behalf of C code.
+///
+/// The backtrace is printed like the one of a panic, as configured through
+/// `RUST_BACKTRACE`, or as a short one if that doesn't enable backtraces, since
+/// the caller asked for it. A panic while printing it is swallowed rather than
+/// unwinding into C, and so are errors writing to stderr.
+pub fn print_for_ffi() {
+    use crate::panic::{self, AssertUnwindSafe};
+    use crate::sys::stdio::{panic_output, panic_output_is_terminal};
+
+    let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+        let verbosity = match log_enabled() {
+            Some(_) => Verbosity::Configured,
+            None => Verbosity::Forced(PrintFmt::Short),
+        };
+        if let Some(mut out) = panic_output() {
+            let _ = print(&mut out, verbosity, panic_output_is_terminal());
         }
+    }));
+}
+
+/// Collects a backtrace being printed, to write it out in as few calls as
+/// possible.
+///
+/// Writing straight to a pipe which fills up could otherwise fail in the middle
+/// of a frame, leaving it garbled, or let other output get in the middle of it.
+/// Backtraces longer than `PRINT_BUFFER_SIZE` are written out in chunks, each
+/// ending at the end of a line.
+///
+/// If `flush_each` is set, every line is written out and flushed as soon as it
+/// is complete instead, so that a process killed in the middle of printing a
+/// backtrace still leaves the frames printed until then behind.
+struct BufferedPrint<'a> {
+    inner: &'a mut dyn Write,
+    buf: Vec<u8>,
+    flush_each: bool,
+    /// Whether writing to `inner` failed.
+    failed: bool,
+    /// A copy of everything written, if it is appended to a file as well.
+    copy: Option<Vec<u8>>,
+}
+
+impl<'a> BufferedPrint<'a> {
+    fn new(inner: &'a mut dyn Write, flush_each: bool) -> BufferedPrint<'a> {
+        BufferedPrint { inner, buf: Vec::new(), flush_each, failed: false, copy: None }
+    }
+
+    /// Writes out whatever is left of the backtrace.
+    fn finish(self) -> io::Result<()> {
+        self.inner.write_all(&self.buf)?;
+        self.inner.flush()
     }
-    write!(w, "{}", DisplayBacktrace { format })
 }
 
-unsafe fn _print_fmt(fmt: &mut fmt::Formatter<'_>, print_fmt: PrintFmt) -> fmt::Result {
-    let cwd = env::current_dir().ok();
-    let mut print_path = move |fmt: &mut fmt::Formatter<'_>, bows: BytesOrWideString<'_>| {
-        output_filename(fmt, bows, print_fmt, cwd.as_ref())
-    };
-    let mut bt_fmt = BacktraceFmt::new(fmt, print_fmt, &mut print_path);
-    bt_fmt.add_context()?;
-    let mut idx = 0;
-    let mut res = Ok(());
-    backtrace_rs::trace_unsynchronized(|frame| {
-        if print_fmt == PrintFmt::Short && idx > MAX_NB_FRAMES {
-            return false;
+impl Write for BufferedPrint<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if let Some(ref mut copy) = self.copy {
+            copy.extend_from_slice(data);
         }
-
-        let mut hit = false;
-        let mut stop = false;
-        backtrace_rs::resolve_frame_unsynchronized(frame, |symbol| {
-            hit = true;
-            if print_fmt == PrintFmt::Short {
-                if let Some(sym) = symbol.name().and_then(|s| s.as_str()) {
-                    if sym.contains("__rust_begin_short_backtrace") {
-                        stop = true;
-                        return;
-                    }
+        if self.flush_each || self.buf.len() >= PRINT_BUFFER_SIZE {
+            if let Some(end) = self.buf.iter().rposition(|&b| b == b'\n') {
+                let res = self.inner.write_all(&self.buf[..=end]).and_then(|()| {
+                    if self.flush_each { self.inner.flush() } else { Ok(()) }
+                });
+                self.buf.drain(..=end);
+                if res.is_err() {
+                    self.failed = true;
+                    return res.map(|()| 0);
                 }
             }
+        }
+        Ok(data.len())
+    }
 
-            res = bt_fmt.frame().symbol(frame, symbol);
-        });
-        if stop {
-            return false;
+    // Only `finish` writes out the end of the backtrace, as that is the only
+    // place it is known not to end in the middle of a line.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Marks the current thread as printing a backtrace until the returned guard is
+/// dropped, or returns `None` if it already is.
+fn enter_print() -> Option<impl Drop> {
+    use crate::cell::Cell;
+
+    thread_local! { static PRINTING: Cell<bool> = Cell::new(false) }
+
+    struct Guard;
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            let _ = PRINTING.try_with(|printing| printing.set(false));
         }
-        if !hit {
-            res = bt_fmt.frame().print_raw(frame.ip(), None, None, None);
+    }
+
+    // Without thread local storage there is no way to tell, so assume this is
+    // the outermost call.
+    match PRINTING.try_with(|printing| printing.replace(true)) {
+        Ok(true) => None,
+        _ => Some(Guard),
+    }
+}
+
+/// Appends `output`, a printed backtrace, to the file at `path`, headed by the
+/// id of the process and the time, so several crashes logged to the same file
+/// can be told apart. The escape sequences coloring the output are left out.
+///
+/// The time is the UTC wall-clock time, like `2019-10-14T12:34:56Z`. If the
+/// system clock is set before 1970, how long the process has been running is
+/// printed instead.
+fn append_to_file(path: &Path, output: &[u8]) -> io::Result<()> {
+    use crate::fs::OpenOptions;
+    use crate::process;
+    use crate::time::{SystemTime, UNIX_EPOCH};
+
+    // The backtrace is written with a single call so it doesn't interleave
+    // with the ones appended by other processes.
+    let mut buf = Vec::with_capacity(output.len() + 64);
+    write!(buf, "backtrace of process {} ", process::id())?;
+    match (SystemTime::now().duration_since(UNIX_EPOCH), start_time()) {
+        (Ok(time), _) => writeln!(buf, "at {}", Timestamp(time.as_secs()))?,
+        (Err(_), Some(start)) => {
+            let uptime = start.elapsed();
+            writeln!(buf, "{}.{:03}s after the process started",
+                     uptime.as_secs(), uptime.subsec_millis())?
         }
+        (Err(_), None) => writeln!(buf, "at an unknown time")?,
+    }
+    let mut rest = output;
+    while let Some(start) = rest.iter().position(|&b| b == 0x1b) {
+        buf.extend_from_slice(&rest[..start]);
+        rest = match rest[start..].iter().position(|&b| b == b'm') {
+            Some(end) => &rest[start + end + 1..],
+            None => &[],
+        };
+    }
+    buf.extend_from_slice(rest);
+    OpenOptions::new().create(true).append(true).open(path)?.write_all(&buf)
+}
 
-        idx += 1;
-        res.is_ok()
-    });
-    res?;
-    bt_fmt.finish()?;
-    if print_fmt == PrintFmt::Short {
-        writeln!(
+/// Displays a number of seconds since the Unix epoch as the UTC date and time
+/// in ISO 8601 format, like `2019-10-14T12:34:56Z`.
+struct Timestamp(u64);
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let days = self.0 / 86400;
+        let secs = self.0 % 86400;
+        // The civil date is computed from the days since 0000-03-01, so leap
+        // days fall at the end of each year, in eras of 400 years.
+        let days = days + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+        write!(
             fmt,
-            "note: Some details are omitted, \
-             run with `RUST_BACKTRACE=full` for a verbose backtrace."
-        )?;
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        )
     }
-    Ok(())
 }
 
-/// Fixed frame used to clean the backtrace with `RUST_BACKTRACE=1`.
-#[inline(never)]
-pub fn __rust_begin_short_backtrace<F, T>(f: F) -> T
-where
-    F: FnOnce() -> T,
-    F: Send,
-    T: Send,
-{
-    f()
+/// Prints the instruction pointers of the current backtrace, one per line.
+///
+/// Unlike `print`, this neither allocates, takes locks nor resolves symbols,
+/// so it can be called from a signal handler. The only system calls issued
+/// are the ones made by `w`. The stack is walked with `_Unwind_Backtrace` on
+/// most platforms, which only reads memory of the process; on Windows it goes
+/// through `dbghelp.dll` instead, which is never async-signal-safe.
+pub fn print_minimal(w: &mut dyn Write) -> io::Result<()> {
+    // See `print` for why this is skipped in tests.
+    if cfg!(test) {
+        return Ok(());
+    }
+
+    let mut res = Ok(());
+    unsafe {
+        backtrace_rs::trace_unsynchronized(|frame| {
+            res = w.write_all(&hex_line(frame.ip() as usize));
+            res.is_ok()
+        });
+    }
+    res
 }
 
-// For now logging is turned off by default, and this function checks to see
-// whether the magical environment variable is present to see if it's turned on.
-pub fn log_enabled() -> Option<PrintFmt> {
-    use crate::sync::atomic::{self, Ordering};
+/// Prints the instruction pointers of the current backtrace like
+/// `print_minimal`, headed by the name and number of the `signal` which
+/// triggered it, like `Process received signal SIGSEGV (11)`.
+///
+/// The name is taken from a static table, so this is as safe to call from a
+/// signal handler as `print_minimal`.
+pub fn print_minimal_for_signal(w: &mut dyn Write, signal: i32) -> io::Result<()> {
+    match signal_name(signal) {
+        Some(name) => write!(w, "Process received signal {} ({})", name, signal)?,
+        None => write!(w, "Process received signal {}", signal)?,
+    }
+    w.write_all(b" - backtrace follows\n")?;
+    print_minimal(w)
+}
+
+/// Returns the name of `signal`, for the signals commonly raised by faults or
+/// sent to a process.
+fn signal_name(signal: i32) -> Option<&'static str> {
+    #[cfg(unix)]
+    const SIGNALS: &[(i32, &str)] = &[
+        (libc::SIGSEGV, "SIGSEGV"),
+        (libc::SIGBUS, "SIGBUS"),
+        (libc::SIGILL, "SIGILL"),
+        (libc::SIGFPE, "SIGFPE"),
+        (libc::SIGABRT, "SIGABRT"),
+        (libc::SIGTRAP, "SIGTRAP"),
+        (libc::SIGSYS, "SIGSYS"),
+        (libc::SIGQUIT, "SIGQUIT"),
+        (libc::SIGTERM, "SIGTERM"),
+        (libc::SIGINT, "SIGINT"),
+        (libc::SIGHUP, "SIGHUP"),
+        (libc::SIGPIPE, "SIGPIPE"),
+        (libc::SIGALRM, "SIGALRM"),
+        (libc::SIGUSR1, "SIGUSR1"),
+        (libc::SIGUSR2, "SIGUSR2"),
+    ];
+    #[cfg(not(unix))]
+    const SIGNALS: &[(i32, &str)] = &[];
+
+    SIGNALS.iter().find(|&&(number, _)| number == signal).map(|&(_, name)| name)
+}
 
-    // Setting environment variables for Fuchsia components isn't a standard
-    // or easily supported workflow. For now, always display backtraces.
-    if cfg!(target_os = "fuchsia") {
-        return Some(PrintFmt::Full);
+/// Counts the frames of the current backtrace without resolving any of them.
+///
+/// If `trim` is set, null frames and the ones from a registered sentinel on
+/// are left out, like in short backtraces. Sentinels are recognized by their
+/// address only, as names aren't known without resolving frames.
+pub fn frame_count(trim: bool) -> usize {
+    // See `print` for why this is skipped in tests.
+    if cfg!(test) {
+        return 0;
     }
 
-    static ENABLED: atomic::AtomicIsize = atomic::AtomicIsize::new(0);
-    match ENABLED.load(Ordering::SeqCst) {
-        0 => {}
-        1 => return None,
-        2 => return Some(PrintFmt::Short),
-        _ => return Some(PrintFmt::Full),
+    let sentinels = if trim { short_backtrace_sentinels() } else { Vec::new() };
+    let mut count = 0;
+    unsafe {
+        let _lock = lock();
+        backtrace_rs::trace_unsynchronized(|frame| {
+            if sentinels.contains(&(frame.symbol_address() as usize)) {
+                return false;
+            }
+            if !trim || !frame.ip().is_null() {
+                count += 1;
+            }
+            true
+        });
     }
+    count
+}
 
-    let val = env::var_os("RUST_BACKTRACE").and_then(|x| {
-        if &x == "0" {
-            None
-        } else if &x == "full" {
-            Some(PrintFmt::Full)
-        } else {
-            Some(PrintFmt::Short)
-        }
-    });
-    ENABLED.store(
-        match val {
-            Some(v) => v as isize,
-            None => 1,
-        },
-        Ordering::SeqCst,
-    );
-    val
+/// Prints the instruction pointers of the current backtrace into `buf`, one per
+/// line, and returns how many bytes were written.
+///
+/// Like `print_minimal` this doesn't allocate, so it can be used when the heap
+/// is unusable. Lines which don't fit into `buf` are left out entirely.
+pub fn print_into(buf: &mut [u8]) -> usize {
+    // See `print` for why this is skipped in tests.
+    if cfg!(test) {
+        return 0;
+    }
+
+    let mut len = 0;
+    unsafe {
+        backtrace_rs::trace_unsynchronized(|frame| {
+            let line = hex_line(frame.ip() as usize);
+            match buf.get_mut(len..len + line.len()) {
+                Some(dst) => {
+                    dst.copy_from_slice(&line);
+                    len += line.len();
+                    true
+                }
+                None => false,
+            }
+        });
+    }
+    len
 }
 
-/// Prints the filename of the backtrace frame.
+/// Formats `addr` as zero padded hex followed by a newline, without going
+/// through `fmt`.
+fn hex_line(mut addr: usize) -> [u8; HEX_WIDTH + 1] {
+    let mut buf = [0u8; HEX_WIDTH + 1];
+    buf[0] = b'0';
+    buf[1] = b'x';
+    for digit in buf[2..HEX_WIDTH].iter_mut().rev() {
+        *digit = b"0123456789abcdef"[addr & 0xf];
+        addr >>= 4;
+    }
+    buf[HEX_WIDTH] = b'\n';
+    buf
+}
+
+/// Hands the instruction pointer, demangled symbol name, filename and line
+/// number of each frame of the current backtrace to `f`.
 ///
-/// See also `output`.
-pub fn output_filename(
-    fmt: &mut fmt::Formatter<'_>,
-    bows: BytesOrWideString<'_>,
-    print_fmt: PrintFmt,
-    cwd: Option<&PathBuf>,
-) -> fmt::Result {
-    let file: Cow<'_, Path> = match bows {
-        #[cfg(unix)]
-        BytesOrWideString::Bytes(bytes) => {
-            use crate::os::unix::prelude::*;
-            Path::new(crate::ffi::OsStr::from_bytes(bytes)).into()
-        }
-        #[cfg(not(unix))]
-        BytesOrWideString::Bytes(bytes) => {
-            Path::new(crate::str::from_utf8(bytes).unwrap_or("<unknown>")).into()
-        }
-        #[cfg(windows)]
-        BytesOrWideString::Wide(wide) => {
-            use crate::os::windows::prelude::*;
-            Cow::Owned(crate::ffi::OsString::from_wide(wide).into())
+/// If `trim` is set, the frames are trimmed like the ones of short backtraces,
+/// otherwise every frame is handed out like in full backtraces. The stack is
+/// walked for as long as `f` returns `FrameControl::Continue`, see there.
+pub fn foreach_frame(
+    trim: bool,
+    f: &mut dyn FnMut(*mut c_void, Option<&str>, Option<&Path>, Option<u32>) -> FrameControl,
+) {
+    // See `print` for why this is skipped in tests.
+    if cfg!(test) {
+        return;
+    }
+
+    let print_fmt = if trim { PrintFmt::Short } else { PrintFmt::Full };
+    // The frame whose remaining inlined symbols are skipped.
+    let mut skipping = None;
+    unsafe {
+        let _lock = lock();
+        // Stopping is passed on as an error, which ends the walk right away.
+        let _ = trace_frames(&FrameSource::Current, print_fmt, false, &mut |traced| {
+            if skipping.take() == Some(traced.ip) {
+                if traced.inlined {
+                    skipping = Some(traced.ip);
+                }
+                return Ok(());
+            }
+            let symbol = traced.symbol;
+            let name = symbol.and_then(|s| s.name()).map(|name| format!("{:#}", name));
+            let file = symbol.and_then(|s| s.filename_raw()).map(bows_to_path);
+            let control = f(
+                traced.ip,
+                name.as_ref().map(|name| &name[..]),
+                file.as_ref().map(|file| &**file),
+                symbol.and_then(|s| s.lineno()),
+            );
+            match control {
+                FrameControl::Continue => Ok(()),
+                FrameControl::Skip => {
+                    if traced.inlined {
+                        skipping = Some(traced.ip);
+                    }
+                    Ok(())
+                }
+                FrameControl::Stop => Err(fmt::Error),
+            }
+        });
+    }
+}
+
+/// Prints the full backtraces of all threads of the process.
+///
+/// Threads with identical stacks are grouped, and each stack is only printed
+/// once, headed by the OS ids of the threads it belongs to. The other threads
+/// are only interrupted to walk their stacks if enabled through
+/// `RUST_BACKTRACE_INTERRUPT_THREADS=1`, see `capture_threads`.
+pub fn print_all_threads(w: &mut dyn Write) -> io::Result<()> {
+    // See `print` for why this is skipped in tests.
+    if cfg!(test) {
+        return Ok(());
+    }
+
+    unsafe {
+        let _lock = lock();
+        let _cwd = remember_current_dir();
+        let mut groups: Vec<(u64, Vec<usize>, Vec<u64>)> = Vec::new();
+        let mut missing = Vec::new();
+        let interrupt = env_flag(Flag::InterruptThreads);
+        for (tid, ips) in capture_threads(interrupt)? {
+            let ips = match ips {
+                Some(ips) => ips,
+                None => {
+                    missing.push(tid);
+                    continue;
+                }
+            };
+            let hash = _hash(&FrameSource::Captured(ips.clone(), Vec::new()), PrintFmt::Full);
+            match groups.iter_mut().find(|group| group.0 == hash) {
+                Some(group) => group.2.push(tid),
+                None => groups.push((hash, ips, vec![tid])),
+            }
         }
-        #[cfg(not(windows))]
-        BytesOrWideString::Wide(_wide) => {
-            Path::new("<unknown>").into()
+
+        for (_, ips, tids) in groups {
+            let tids = tids.iter().map(|tid| tid.to_string()).collect::<Vec<_>>();
+            let plural = if tids.len() == 1 { "" } else { "s" };
+            writeln!(w, "thread{} {}:", plural, tids.join(", "))?;
+            let source = FrameSource::Captured(ips, Vec::new());
+            _print(w, &source, PrintFmt::Full, OutputStyle::Text, false, false)?;
         }
-    };
-    if print_fmt == PrintFmt::Short && file.is_absolute() {
-        if let Some(cwd) = cwd {
-            if let Ok(stripped) = file.strip_prefix(&cwd) {
-                if let Some(s) = stripped.to_str() {
-                    return write!(fmt, ".{}{}", path::MAIN_SEPARATOR, s);
-                }
+        for tid in missing {
+            if interrupt {
+                writeln!(w, "thread {}: no backtrace, the thread didn't respond", tid)?;
+            } else {
+                writeln!(w, "thread {}: no backtrace, run with \
+                             `RUST_BACKTRACE_INTERRUPT_THREADS=1` to interrupt it", tid)?;
             }
         }
+        Ok(())
+    }
+}
+
+thread_local! {
+    static CURRENT_DIR: crate::cell::RefCell<Option<Option<PathBuf>>> =
+        crate::cell::RefCell::new(None)
+}
+
+/// Looks up the current directory once for all backtraces printed by the
+/// current thread until the returned guard is dropped, see `current_dir`.
+fn remember_current_dir() -> impl Drop {
+    struct Guard;
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            let _ = CURRENT_DIR.try_with(|dir| *dir.borrow_mut() = None);
+        }
+    }
+
+    let _ = CURRENT_DIR.try_with(|dir| *dir.borrow_mut() = Some(env::current_dir().ok()));
+    Guard
+}
+
+/// Returns the directory paths are printed relative to, which is looked up
+/// anew for every backtrace unless a dump of several remembered it.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn current_dir() -> Option<PathBuf> {
+    match CURRENT_DIR.try_with(|dir| dir.borrow().clone()) {
+        Ok(Some(dir)) => dir,
+        _ => env::current_dir().ok(),
+    }
+}
+
+/// Captures the instruction pointers of the stacks of all threads of the
+/// process, by having each of them walk its stack in a signal handler if
+/// `interrupt` is set. Otherwise, only the current thread's stack is walked.
+///
+/// Threads are identified by their OS id. Threads which didn't walk their
+/// stack in time, for instance because they block the signal, are listed
+/// without one.
+///
+/// `SIGURG` is used to interrupt the threads, as it is ignored by default and
+/// rarely used. While the stacks are captured, a handler installed for it by
+/// the program is replaced, and restored afterwards. Walking the stack isn't
+/// async-signal-safe: `_Unwind_Backtrace` takes the lock of the dynamic
+/// loader, so a thread interrupted while loading a library deadlocks. Such a
+/// thread is given up on, and as it may still write its frames later, no other
+/// thread is interrupted by this or any later capture.
+#[cfg(target_os = "linux")]
+unsafe fn capture_threads(interrupt: bool) -> io::Result<Vec<(u64, Option<Vec<usize>>)>> {
+    use crate::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Max number of frames captured per thread.
+    const MAX_CAPTURED_FRAMES: usize = 256;
+    /// How long a thread gets to walk its stack.
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    // The state is the OS id of the thread asked to walk its stack, or one of
+    // the following, which are too large to be an OS id.
+    const IDLE: usize = usize::max_value();
+    const WRITING: usize = usize::max_value() - 1;
+    const DONE: usize = usize::max_value() - 2;
+    static STATE: AtomicUsize = AtomicUsize::new(IDLE);
+    // Written by the signal handler while `STATE` is `WRITING`, only.
+    static mut IPS: [usize; MAX_CAPTURED_FRAMES] = [0; MAX_CAPTURED_FRAMES];
+    static mut LEN: usize = 0;
+
+    // Neither allocates nor takes locks, only the thread asked for proceeds
+    // as a late signal to another thread would overwrite `IPS`.
+    extern "C" fn handler(_signum: libc::c_int) {
+        unsafe {
+            let tid = libc::syscall(libc::SYS_gettid) as usize;
+            if STATE.compare_exchange(tid, WRITING, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+                return;
+            }
+            let mut len = 0;
+            backtrace_rs::trace_unsynchronized(|frame| {
+                IPS[len] = frame.ip() as usize;
+                len += 1;
+                len < MAX_CAPTURED_FRAMES
+            });
+            LEN = len;
+            STATE.store(DONE, Ordering::SeqCst);
+        }
+    }
+
+    let mut tids = Vec::new();
+    for entry in crate::fs::read_dir("/proc/self/task")? {
+        if let Some(tid) = entry?.file_name().to_str().and_then(|s| s.parse::<u64>().ok()) {
+            tids.push(tid);
+        }
+    }
+    tids.sort();
+
+    let mut old: libc::sigaction = mem::zeroed();
+    if interrupt {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handler as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(libc::SIGURG, &action, &mut old) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    let pid = libc::getpid();
+    let own = libc::syscall(libc::SYS_gettid) as u64;
+    // Once a thread got stuck walking its stack, `IPS` can't be reused, even
+    // by a later capture, unless the thread finished after all.
+    let mut stuck = match STATE.load(Ordering::SeqCst) {
+        IDLE | DONE => false,
+        _ => true,
+    };
+    let mut threads = Vec::new();
+    for tid in tids {
+        if tid == own {
+            let mut ips = Vec::new();
+            backtrace_rs::trace_unsynchronized(|frame| {
+                ips.push(frame.ip() as usize);
+                ips.len() < MAX_CAPTURED_FRAMES
+            });
+            threads.push((tid, Some(ips)));
+            continue;
+        }
+        if !interrupt || stuck {
+            threads.push((tid, None));
+            continue;
+        }
+
+        STATE.store(tid as usize, Ordering::SeqCst);
+        let mut captured = libc::syscall(libc::SYS_tgkill, pid, tid, libc::SIGURG) == 0;
+        let start = Instant::now();
+        while captured && STATE.load(Ordering::SeqCst) != DONE {
+            if start.elapsed() > TIMEOUT {
+                // Unless the thread already started walking its stack, it
+                // won't anymore.
+                let state = tid as usize;
+                match STATE.compare_exchange(state, IDLE, Ordering::SeqCst, Ordering::SeqCst) {
+                    Ok(_) => captured = false,
+                    Err(DONE) => {}
+                    Err(_) => {
+                        stuck = true;
+                        captured = false;
+                    }
+                }
+                break;
+            }
+            crate::thread::sleep(Duration::from_millis(1));
+        }
+        threads.push((tid, if captured { Some(IPS[..LEN].to_vec()) } else { None }));
+        if !stuck {
+            STATE.store(IDLE, Ordering::SeqCst);
+        }
+    }
+
+    if interrupt {
+        libc::sigaction(libc::SIGURG, &old, crate::ptr::null_mut());
+    }
+    Ok(threads)
+}
+
+#[cfg(not(target_os = "linux"))]
+unsafe fn capture_threads(_interrupt: bool) -> io::Result<Vec<(u64, Option<Vec<usize>>)>> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "capturing the backtraces of other threads is not supported on this platform",
+    ))
+}
+
+/// Prints the full backtrace of the stack the machine context at `ctx` was
+/// saved from, such as the `ucontext_t` handed to a signal handler or the
+/// `CONTEXT` of an exception record, rather than of the current one.
+///
+/// The frames are printed like the ones of `print_all_threads`. Fails with an
+/// error of kind `ErrorKind::Other` where walking the stack from a context
+/// isn't supported.
+#[cfg(any(unix, windows))]
+pub unsafe fn print_from_context(w: &mut dyn Write, ctx: *mut c_void) -> io::Result<()> {
+    let ips = trace_from_context(ctx)?;
+    let _lock = lock();
+    let source = FrameSource::Captured(ips, Vec::new());
+    _print(w, &source, PrintFmt::Full, OutputStyle::Text, false, false)
+}
+
+/// Returns the instruction pointers of the stack the machine context at `ctx`
+/// was saved from, innermost first.
+///
+/// `backtrace` only walks the stack of the current thread from where it is
+/// called, and doesn't take a context to start from yet, on any platform.
+/// Unwinding from the instruction and frame pointers alone would go wrong in
+/// code built without frame pointers, like the standard library itself, so
+/// nothing is attempted until it does.
+#[cfg(any(unix, windows))]
+unsafe fn trace_from_context(_ctx: *mut c_void) -> io::Result<Vec<usize>> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "walking the stack from a saved context is not supported on this platform",
+    ))
+}
+
+/// Computes a fingerprint of the current backtrace.
+///
+/// Only the demangled names of the frames a short backtrace would print are
+/// taken into account, so the hash doesn't depend on addresses or line numbers
+/// and stays the same across runs of the same binary.
+pub fn hash() -> u64 {
+    // See `print` for why this is skipped in tests.
+    if cfg!(test) {
+        return 0;
+    }
+
+    unsafe {
+        let _lock = lock();
+        _hash(&FrameSource::Current, PrintFmt::Short)
+    }
+}
+
+unsafe fn _hash(source: &FrameSource, print_fmt: PrintFmt) -> u64 {
+    use crate::collections::hash_map::DefaultHasher;
+    use crate::hash::Hasher;
+
+    struct HashWriter<'a>(&'a mut DefaultHasher);
+
+    impl fmt::Write for HashWriter<'_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.0.write(s.as_bytes());
+            Ok(())
+        }
+    }
+
+    // `DefaultHasher::new` always uses the same keys, unlike `RandomState`.
+    let mut hasher = DefaultHasher::new();
+    let _ = trace_frames(source, print_fmt, false, &mut |traced| {
+        match traced.symbol.and_then(|s| s.name()) {
+            Some(name) => fmt::write(&mut HashWriter(&mut hasher), format_args!("{:#}", name))?,
+            None => hasher.write(b"<unknown>"),
+        }
+        // Separate the names so frames can't run into each other.
+        hasher.write_u8(0xff);
+        Ok(())
+    });
+    hasher.finish()
+}
+
+/// Prints the frames of `source` in the given format and layout to `w`.
+///
+/// With the `backtrace-minimal` feature, this always prints bare instruction
+/// pointers like `OutputStyle::Addrs` instead, so that none of the code
+/// resolving and formatting frames ends up in the binary.
+#[cfg(feature = "backtrace-minimal")]
+unsafe fn _print(
+    w: &mut dyn Write,
+    source: &FrameSource,
+    format: PrintFmt,
+    _style: OutputStyle,
+    _color: bool,
+    _overflowed: bool,
+) -> io::Result<()> {
+    struct DisplayAddrs<'a> {
+        source: &'a FrameSource,
+        format: PrintFmt,
+    }
+    impl fmt::Display for DisplayAddrs<'_> {
+        fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+            fmt.write_str("stack backtrace:\n")?;
+            unsafe { _print_addrs_fmt(fmt, self.source, self.format) }
+        }
+    }
+    write!(w, "{}", DisplayAddrs { source, format })
+}
+
+/// Prints the frames of `source` in the given format and layout to `w`.
+///
+/// `overflowed` heads text backtraces with a note that frames may be missing
+/// because the stack overflowed.
+#[cfg(not(feature = "backtrace-minimal"))]
+unsafe fn _print(
+    w: &mut dyn Write,
+    source: &FrameSource,
+    format: PrintFmt,
+    style: OutputStyle,
+    color: bool,
+    overflowed: bool,
+) -> io::Result<()> {
+    struct DisplayBacktrace<'a> {
+        source: &'a FrameSource,
+        format: PrintFmt,
+        style: OutputStyle,
+        color: bool,
+        overflowed: bool,
+    }
+    impl fmt::Display for DisplayBacktrace<'_> {
+        fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+            unsafe {
+                match self.style {
+                    OutputStyle::Text => match line_prefix() {
+                        Some(prefix) => {
+                            let mut fmt = LinePrefix::new(fmt, prefix);
+                            fmt::Write::write_fmt(&mut fmt, format_args!("{}", PrintText(self)))
+                        }
+                        None => {
+                            _print_fmt(fmt, self.source, self.format, self.color, self.overflowed)
+                        }
+                    },
+                    OutputStyle::Json => _print_json_fmt(fmt, self.source, self.format),
+                    OutputStyle::Compact => _print_compact_fmt(fmt, self.source, self.format),
+                    OutputStyle::Addrs => _print_addrs_fmt(fmt, self.source, self.format),
+                }
+            }
+        }
+    }
+    struct PrintText<'a, 'b>(&'a DisplayBacktrace<'b>);
+    impl fmt::Display for PrintText<'_, '_> {
+        fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let bt = self.0;
+            unsafe { _print_fmt(fmt, bt.source, bt.format, bt.color, bt.overflowed) }
+        }
+    }
+    let bt = DisplayBacktrace { source, format, style, color, overflowed };
+    if crate::backtrace::line_hook_installed() {
+        let mut w = HookLines::new(w);
+        write!(w, "{}", bt)?;
+        w.finish()
+    } else {
+        write!(w, "{}", bt)
+    }
+}
+
+/// Formats the frames of a backtrace captured earlier the way `print` prints
+/// the current one, for `std::backtrace::Backtrace`.
+///
+/// `ips` are the instruction pointers of the frames, innermost first, and
+/// `spawned_at` the indices of the ones at which the backtraces chained on to
+/// it begin. The caller has to hold the lock, see `lock`.
+#[cfg(feature = "backtrace-minimal")]
+pub unsafe fn fmt_captured(
+    fmt: &mut fmt::Formatter<'_>,
+    ips: Vec<usize>,
+    _spawned_at: Vec<usize>,
+    print_fmt: PrintFmt,
+) -> fmt::Result {
+    fmt.write_str("stack backtrace:\n")?;
+    _print_addrs_fmt(fmt, &FrameSource::Captured(ips, Vec::new()), print_fmt)
+}
+
+/// Formats the frames of a backtrace captured earlier the way `print` prints
+/// the current one, for `std::backtrace::Backtrace`.
+///
+/// `ips` are the instruction pointers of the frames, innermost first, and
+/// `spawned_at` the indices of the ones at which the backtraces chained on to
+/// it begin. The caller has to hold the lock, see `lock`.
+#[cfg(not(feature = "backtrace-minimal"))]
+pub unsafe fn fmt_captured(
+    fmt: &mut fmt::Formatter<'_>,
+    ips: Vec<usize>,
+    spawned_at: Vec<usize>,
+    print_fmt: PrintFmt,
+) -> fmt::Result {
+    _print_fmt(fmt, &FrameSource::Captured(ips, spawned_at), print_fmt, false, false)
+}
+
+/// Writes each line through the hook installed through
+/// `std::backtrace::set_line_hook`, as soon as the line is complete.
+#[cfg(not(feature = "backtrace-minimal"))]
+struct HookLines<'a> {
+    inner: &'a mut dyn Write,
+    line: Vec<u8>,
+}
+
+#[cfg(not(feature = "backtrace-minimal"))]
+impl<'a> HookLines<'a> {
+    fn new(inner: &'a mut dyn Write) -> HookLines<'a> {
+        HookLines { inner, line: Vec::new() }
+    }
+
+    fn write_line(&mut self, end: &[u8]) -> io::Result<()> {
+        let line = String::from_utf8_lossy(&self.line);
+        match crate::backtrace::hook_line(&line) {
+            Some(hooked) => self.inner.write_all(hooked.as_bytes())?,
+            None => self.inner.write_all(line.as_bytes())?,
+        }
+        self.inner.write_all(end)
+    }
+
+    /// Writes out the last line, if it didn't end in a newline.
+    fn finish(mut self) -> io::Result<()> {
+        if self.line.is_empty() { Ok(()) } else { self.write_line(b"") }
+    }
+}
+
+#[cfg(not(feature = "backtrace-minimal"))]
+impl Write for HookLines<'_> {
+    fn write(&mut self, mut data: &[u8]) -> io::Result<usize> {
+        let len = data.len();
+        while let Some(end) = data.iter().position(|&b| b == b'\n') {
+            self.line.extend_from_slice(&data[..end]);
+            self.write_line(b"\n")?;
+            self.line.clear();
+            data = &data[end + 1..];
+        }
+        self.line.extend_from_slice(data);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes to a formatter with `prefix` put in front of every line, as
+/// configured through `RUST_BACKTRACE_LINE_PREFIX`.
+#[cfg(not(feature = "backtrace-minimal"))]
+struct LinePrefix<'a, 'b> {
+    fmt: &'a mut fmt::Formatter<'b>,
+    prefix: &'a str,
+    line_start: bool,
+}
+
+#[cfg(not(feature = "backtrace-minimal"))]
+impl<'a, 'b> LinePrefix<'a, 'b> {
+    fn new(fmt: &'a mut fmt::Formatter<'b>, prefix: &'a str) -> LinePrefix<'a, 'b> {
+        LinePrefix { fmt, prefix, line_start: true }
+    }
+}
+
+#[cfg(not(feature = "backtrace-minimal"))]
+impl fmt::Write for LinePrefix<'_, '_> {
+    fn write_str(&mut self, mut s: &str) -> fmt::Result {
+        while !s.is_empty() {
+            if self.line_start {
+                self.fmt.write_str(self.prefix)?;
+            }
+            let (line, rest) = match s.find('\n') {
+                Some(end) => s.split_at(end + 1),
+                None => (s, ""),
+            };
+            self.fmt.write_str(line)?;
+            self.line_start = line.ends_with('\n');
+            s = rest;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "backtrace-minimal"))]
+unsafe fn _print_fmt(
+    fmt: &mut fmt::Formatter<'_>,
+    source: &FrameSource,
+    print_fmt: PrintFmt,
+    color: bool,
+    overflowed: bool,
+) -> fmt::Result {
+    let cwd = current_dir();
+    let prefixes = strip_prefixes();
+    // The header and footer may carry platform specific context (such as the
+    // symbolizer markup on Fuchsia), so leave those to `BacktraceFmt`. Neither
+    // of them contains any paths, those are printed by `FrameFmt` below.
+    let mut print_path =
+        |_: &mut fmt::Formatter<'_>, _: BytesOrWideString<'_>| -> fmt::Result { Ok(()) };
+    let markers = env_flag(Flag::Markers);
+    if markers {
+        print_marker(fmt, "BEGIN")?;
+    }
+    if let FrameSource::Current = *source {
+        print_uptime(fmt)?;
+        print_thread_header(fmt)?;
+    }
+    if overflowed {
+        writeln!(fmt, "note: stack overflowed; backtrace may be incomplete near the top")?;
+    }
+    BacktraceFmt::new(fmt, print_fmt, &mut print_path).add_context()?;
+    let mut frame_fmt = FrameFmt::new(fmt, print_fmt, color, cwd.as_ref(), prefixes);
+    frame_fmt.source = print_fmt == PrintFmt::Full && env_flag(Flag::Source);
+    frame_fmt.demangle = demangle_style();
+    frame_fmt.delimiter = frame_delimiter();
+    frame_fmt.regs = print_fmt == PrintFmt::Full && env_flag(Flag::Regs);
+    frame_fmt.classify = print_fmt == PrintFmt::Short && env_flag(Flag::UserFrames);
+    frame_fmt.symbols_only = symbols_enabled();
+    frame_fmt.tree = env_flag(Flag::Tree);
+    frame_fmt.align = if print_fmt == PrintFmt::Full { align_column() } else { None };
+    let stats = if env_flag(Flag::Reverse) {
+        print_frames_reversed(source, &mut frame_fmt)?
+    } else {
+        print_frames(source, &mut frame_fmt)?
+    };
+    BacktraceFmt::new(fmt, print_fmt, &mut print_path).finish()?;
+    if !env_flag(Flag::Quiet) {
+        print_summary(fmt, print_fmt, color, &stats)?;
+    }
+    if markers {
+        print_marker(fmt, "END")?;
+    }
+    Ok(())
+}
+
+/// Prints the notes following a backtrace, about which frames were left out
+/// of it.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn print_summary(
+    fmt: &mut fmt::Formatter<'_>,
+    print_fmt: PrintFmt,
+    color: bool,
+    stats: &TraceStats,
+) -> fmt::Result {
+    // Frames left unresolved because of the time budget say nothing about
+    // the debug info, so only a trace resolved in full warrants the note.
+    if !stats.resolved_any && stats.unresolved == 0 && stats.shown > 0 {
+        writeln!(
+            fmt,
+            "note: no symbol information found; ensure the binary wasn't stripped and try \
+             {}=full",
+            BACKTRACE_VAR
+        )?;
+    }
+    // The frame count is only worth a note if frames were left out which
+    // would have been printed otherwise.
+    if stats.capped.is_some() || stats.filtered > 0 {
+        write!(fmt, "note: backtrace contained {} frames ({} shown", stats.total, stats.shown)?;
+        if stats.trimmed > 0 {
+            write!(fmt, ", {} trimmed at `{}`", stats.trimmed, SHORT_BACKTRACE_SENTINEL)?;
+        }
+        writeln!(fmt, ")")?;
+    }
+    if env_flag(Flag::Verbose) {
+        write!(fmt, "note: {}/{} frames had source locations", stats.located, stats.shown)?;
+        if stats.located == 0 && stats.shown > 0 {
+            fmt.write_str(" (debug info likely stripped)")?;
+        }
+        writeln!(fmt)?;
+    }
+    if stats.unresolved > 0 {
+        writeln!(
+            fmt,
+            "note: {} frames weren't resolved as `RUST_BACKTRACE_TIMEOUT_MS` was exceeded",
+            stats.unresolved
+        )?;
+    }
+    if print_fmt == PrintFmt::Full && stats.capped.is_some() {
+        paint(fmt, color, YELLOW)?;
+        write!(
+            fmt,
+            "note: Frames past the first {} are omitted, \
+             set `RUST_BACKTRACE_FULL_LIMIT` to print more.",
+            stats.shown
+        )?;
+        paint(fmt, color, RESET)?;
+        writeln!(fmt)?;
+    }
+    if print_fmt == PrintFmt::Short {
+        paint(fmt, color, YELLOW)?;
+        if let Some(limit) = stats.capped {
+            let var = if is_trimmed(print_fmt) {
+                "RUST_BACKTRACE_LIMIT"
+            } else {
+                "RUST_BACKTRACE_FULL_LIMIT"
+            };
+            writeln!(
+                fmt,
+                "note: reached frame limit ({}); increase `{}` to print more.",
+                limit, var
+            )?;
+        }
+        write!(
+            fmt,
+            "note: Some details are omitted, \
+             run with `{}=full` for a verbose backtrace.",
+            BACKTRACE_VAR
+        )?;
+        paint(fmt, color, RESET)?;
+        writeln!(fmt)?;
+    }
+    Ok(())
+}
+
+/// Prints the frames of `source`, innermost first.
+#[cfg(not(feature = "backtrace-minimal"))]
+unsafe fn print_frames(
+    source: &FrameSource,
+    frame_fmt: &mut FrameFmt<'_, '_>,
+) -> Result<TraceStats, fmt::Error> {
+    let mut origin = PanicOrigin::new(frame_fmt.print_fmt);
+    let mut boundary = CatchBoundary::new();
+    let mut executor = ExecutorBoundary::new(executor_patterns());
+    // The frames trimmed off backtraces with another one chained on to them
+    // are pointed out before the separator, only the rest at the end.
+    let mut chained_trimmed = 0;
+    let stats = trace_frames(source, frame_fmt.print_fmt, true, &mut |traced| {
+        if traced.repeats > 0 {
+            frame_fmt.print_repeated(traced.repeats + 1)?;
+        }
+        if let Some(trimmed) = traced.spawned {
+            if trimmed > 0 {
+                frame_fmt.print_trimmed(trimmed)?;
+            }
+            frame_fmt.print_separator("spawned at")?;
+            chained_trimmed += trimmed;
+        }
+        if traced.hidden > 0 {
+            frame_fmt.print_hidden(traced.hidden)?;
+        }
+        if executor.is_boundary(traced.symbol) {
+            frame_fmt.print_separator("executor boundary")?;
+        }
+        frame_fmt.frame_index = traced.idx;
+        frame_fmt.origin = origin.is_origin(traced.symbol);
+        frame_fmt.caught = boundary.is_boundary(traced.symbol);
+        match traced.symbol {
+            Some(symbol) => frame_fmt.print_raw(
+                traced.ip,
+                traced.inlined,
+                symbol.name(),
+                symbol.addr(),
+                symbol.filename_raw(),
+                symbol.lineno(),
+            )?,
+            None if traced.unresolved => frame_fmt.print_unresolved(traced.ip)?,
+            None => frame_fmt.print_raw(traced.ip, false, None, None, None, None)?,
+        }
+        Ok(())
+    })?;
+    if stats.repeats > 0 {
+        frame_fmt.print_repeated(stats.repeats + 1)?;
+    }
+    if stats.hidden > 0 {
+        frame_fmt.print_hidden(stats.hidden)?;
+    }
+    if stats.trimmed > chained_trimmed {
+        frame_fmt.print_trimmed(stats.trimmed - chained_trimmed)?;
+    }
+    Ok(stats)
+}
+
+/// Prints the frames of `source`, innermost last.
+///
+/// The stack can only be walked from the innermost frame, so all frames are
+/// collected first and then printed in reverse, keeping their original index.
+#[cfg(not(feature = "backtrace-minimal"))]
+unsafe fn print_frames_reversed(
+    source: &FrameSource,
+    frame_fmt: &mut FrameFmt<'_, '_>,
+) -> Result<TraceStats, fmt::Error> {
+    struct BufferedFrame {
+        idx: usize,
+        ip: *mut c_void,
+        symbol: Option<ResolvedSymbol>,
+        inlined: bool,
+        unresolved: bool,
+        origin: bool,
+        caught: bool,
+        executor: bool,
+        hidden: usize,
+        repeats: usize,
+        spawned: Option<usize>,
+    }
+
+    let mut origin = PanicOrigin::new(frame_fmt.print_fmt);
+    let mut boundary = CatchBoundary::new();
+    let mut executor = ExecutorBoundary::new(executor_patterns());
+    let mut frames: Vec<BufferedFrame> = Vec::new();
+    let stats = trace_frames(source, frame_fmt.print_fmt, true, &mut |traced| {
+        if let Some(prev) = frames.last_mut() {
+            prev.repeats = traced.repeats;
+        }
+        frames.push(BufferedFrame {
+            idx: traced.idx,
+            ip: traced.ip,
+            symbol: traced.symbol.cloned(),
+            inlined: traced.inlined,
+            unresolved: traced.unresolved,
+            origin: origin.is_origin(traced.symbol),
+            caught: boundary.is_boundary(traced.symbol),
+            executor: executor.is_boundary(traced.symbol),
+            hidden: traced.hidden,
+            repeats: 0,
+            spawned: traced.spawned,
+        });
+        Ok(())
+    })?;
+    if let Some(last) = frames.last_mut() {
+        last.repeats = stats.repeats;
+    }
+
+    let chained_trimmed: usize = frames.iter().filter_map(|frame| frame.spawned).sum();
+    if stats.trimmed > chained_trimmed {
+        frame_fmt.print_trimmed(stats.trimmed - chained_trimmed)?;
+    }
+    if stats.hidden > 0 {
+        frame_fmt.print_hidden(stats.hidden)?;
+    }
+    for frame in frames.iter().rev() {
+        frame_fmt.frame_index = frame.idx;
+        frame_fmt.origin = frame.origin;
+        frame_fmt.caught = frame.caught;
+        match &frame.symbol {
+            Some(symbol) => frame_fmt.print_raw(
+                frame.ip,
+                frame.inlined,
+                symbol.name(),
+                symbol.addr(),
+                symbol.filename_raw(),
+                symbol.lineno(),
+            )?,
+            None if frame.unresolved => frame_fmt.print_unresolved(frame.ip)?,
+            None => frame_fmt.print_raw(frame.ip, false, None, None, None, None)?,
+        }
+        if frame.repeats > 0 {
+            frame_fmt.print_repeated(frame.repeats + 1)?;
+        }
+        // The boundary goes between the executor and the frames it polls, now
+        // printed after it.
+        if frame.executor {
+            frame_fmt.print_separator("executor boundary")?;
+        }
+        if frame.hidden > 0 {
+            frame_fmt.print_hidden(frame.hidden)?;
+        }
+        // The backtrace this one is chained on to is printed after it.
+        if let Some(trimmed) = frame.spawned {
+            frame_fmt.print_separator("spawned at")?;
+            if trimmed > 0 {
+                frame_fmt.print_trimmed(trimmed)?;
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// Finds the frame a panic originated from, the first one after the functions
+/// implementing `panic!` and `unwrap`, to point it out in short backtraces.
+#[cfg(not(feature = "backtrace-minimal"))]
+struct PanicOrigin {
+    enabled: bool,
+    in_panic: bool,
+}
+
+#[cfg(not(feature = "backtrace-minimal"))]
+impl PanicOrigin {
+    fn new(print_fmt: PrintFmt) -> PanicOrigin {
+        let enabled = print_fmt == PrintFmt::Short && !env_flag(Flag::Quiet);
+        PanicOrigin { enabled, in_panic: false }
+    }
+
+    /// Returns whether `symbol` is the origin of the panic, given the symbols
+    /// of the stack innermost first.
+    fn is_origin(&mut self, symbol: Option<&ResolvedSymbol>) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let name = match symbol.and_then(|s| s.name()) {
+            Some(name) => name,
+            // Unresolved frames within the panic machinery are passed over.
+            None => return false,
+        };
+        if is_panic_symbol(&name) {
+            self.in_panic = true;
+            false
+        } else if self.in_panic {
+            self.enabled = false;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Returns whether `name` is one of the functions `panic!`, `unwrap` and their
+/// relatives go through to start panicking.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn is_panic_symbol(name: &SymbolName<'_>) -> bool {
+    let demangled = format!("{:#}", name);
+    let path = demangled.trim_start_matches('<');
+    path == "rust_begin_unwind"
+        || path.starts_with("core::panicking::")
+        // `catch_unwind` goes through `std::panicking::try` to stop panics.
+        || path.starts_with("std::panicking::") && !path.starts_with("std::panicking::try")
+        || path.starts_with("core::option::expect_failed")
+        || path.starts_with("core::result::unwrap_failed")
+        || (path.starts_with("core::option::Option") || path.starts_with("core::result::Result"))
+            && (path.ends_with("::unwrap") || path.ends_with("::expect"))
+}
+
+/// Finds the frame unwinding from a panic would stop at, the innermost one of
+/// `catch_unwind`, to point it out in backtraces.
+#[cfg(not(feature = "backtrace-minimal"))]
+struct CatchBoundary {
+    enabled: bool,
+}
+
+#[cfg(not(feature = "backtrace-minimal"))]
+impl CatchBoundary {
+    fn new() -> CatchBoundary {
+        CatchBoundary { enabled: !env_flag(Flag::Quiet) }
+    }
+
+    /// Returns whether `symbol` is where unwinding stops, given the symbols of
+    /// the stack innermost first.
+    fn is_boundary(&mut self, symbol: Option<&ResolvedSymbol>) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        match symbol.and_then(|s| s.name()) {
+            Some(ref name) if is_catch_symbol(name) => {
+                self.enabled = false;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Finds the frame at which the executor of an async runtime takes over from the
+/// futures it polls, the innermost one matching any of the patterns of
+/// `executor_patterns`, to set the two apart in backtraces.
+///
+/// Which task the futures belong to can't be told without the help of the
+/// runtime, so this only draws a line between the synchronous frames and the
+/// executor.
+#[cfg(not(feature = "backtrace-minimal"))]
+struct ExecutorBoundary<'a> {
+    patterns: &'a [String],
+}
+
+#[cfg(not(feature = "backtrace-minimal"))]
+impl<'a> ExecutorBoundary<'a> {
+    fn new(patterns: &'a [String]) -> ExecutorBoundary<'a> {
+        ExecutorBoundary { patterns }
+    }
+
+    /// Returns whether `symbol` is where the executor begins, given the symbols
+    /// of the stack innermost first.
+    fn is_boundary(&mut self, symbol: Option<&ResolvedSymbol>) -> bool {
+        if self.patterns.is_empty() {
+            return false;
+        }
+        let name = match symbol.and_then(|s| s.name()) {
+            Some(name) => format!("{:#}", name),
+            None => return false,
+        };
+        if self.patterns.iter().any(|pattern| name.contains(&pattern[..])) {
+            self.patterns = &[];
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Returns whether `name` is one of the functions `catch_unwind` goes through
+/// to catch panics.
+///
+/// The hash of legacy symbols and the generic parameters v0 symbols are
+/// printed with are left out of the comparison, as is the hash of names which
+/// couldn't be demangled.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn is_catch_symbol(name: &SymbolName<'_>) -> bool {
+    let demangled = format!("{:#}", name);
+    let path = demangled.split("::<").next().unwrap_or("");
+    let hash = path.rfind("::h").filter(|&i| {
+        let hash = &path[i + 3..];
+        hash.len() == 16 && hash.chars().all(|c| c.is_ascii_hexdigit())
+    });
+    let path = hash.map_or(path, |i| &path[..i]);
+    path == "__rust_try" || path == "__rust_maybe_catch_panic" || path == "std::panicking::try"
+}
+
+/// Prints a line delimiting a backtrace, tagged with the ids of the process and
+/// thread it belongs to, see `Flag::Markers`.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn print_marker(fmt: &mut fmt::Formatter<'_>, kind: &str) -> fmt::Result {
+    write!(fmt, "===== {} BACKTRACE (process {}", kind, crate::process::id())?;
+    if let Some(thread) = thread_info::current_thread() {
+        write!(fmt, ", thread {}", thread.id().as_u64())?;
+    }
+    writeln!(fmt, ") =====")
+}
+
+/// Prints how long the process has been running, if enabled through
+/// `RUST_BACKTRACE_UPTIME=1` and the time it started at is known.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn print_uptime(fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match start_time() {
+        Some(start) if env_flag(Flag::Uptime) => {
+            let uptime = start.elapsed();
+            writeln!(fmt, "backtrace taken {}.{:03}s after the process started",
+                     uptime.as_secs(), uptime.subsec_millis())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Records the time the process started at, if backtraces print it.
+///
+/// Called by the runtime before `main`, other programs (like the ones written
+/// in other languages using a Rust library) have no start time recorded.
+pub fn init_start_time() {
+    if env_flag(Flag::Uptime) {
+        start_time();
+    }
+}
+
+/// Returns the time the process started at, or records the current time as
+/// such if `init_start_time` wasn't called before.
+fn start_time() -> Option<Instant> {
+    use crate::sync::Once;
+
+    static INIT: Once = Once::new();
+    static mut START: Option<Instant> = None;
+    unsafe {
+        INIT.call_once(|| START = Some(Instant::now()));
+        START
+    }
+}
+
+/// Prints which thread the backtrace belongs to, by its name if it has one and
+/// its id.
+///
+/// The id the operating system knows the thread by is included where it can be
+/// looked up, to correlate the backtrace with tools like debuggers.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn print_thread_header(fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let thread = match thread_info::current_thread() {
+        Some(thread) => thread,
+        // Thread local storage is already gone, so there's nothing to print.
+        None => return Ok(()),
+    };
+    match thread.name() {
+        Some(name) => write!(fmt, "backtrace (thread \"{}\", id {}", name, thread.id().as_u64())?,
+        None => write!(fmt, "backtrace (thread {}", thread.id().as_u64())?,
+    }
+    if let Some(os_id) = crate::sys::thread::Thread::os_id() {
+        write!(fmt, ", os id {}", os_id)?;
+    }
+    writeln!(fmt, "):")
+}
+
+/// Prints the current backtrace as a JSON array with one object per symbol.
+///
+/// Inlined functions share the `index` and `address` of the frame they were
+/// inlined into, and frames which couldn't be resolved at all are emitted with
+/// `null` in place of their symbol information.
+#[cfg(not(feature = "backtrace-minimal"))]
+unsafe fn _print_json_fmt(
+    fmt: &mut fmt::Formatter<'_>,
+    source: &FrameSource,
+    print_fmt: PrintFmt,
+) -> fmt::Result {
+    let cwd = current_dir();
+    let prefixes = strip_prefixes();
+    let mut first = true;
+    fmt.write_str("[")?;
+    trace_frames(source, print_fmt, false, &mut |TracedFrame { idx, ip, symbol, .. }| {
+        fmt.write_str(if first { "\n" } else { ",\n" })?;
+        first = false;
+
+        write!(fmt, "{{\"index\":{},\"symbol_name\":", idx)?;
+        match symbol.and_then(|s| s.name()) {
+            Some(name) if print_fmt == PrintFmt::Short => {
+                write_json_str(fmt, format_args!("{:#}", name))?
+            }
+            Some(name) => write_json_str(fmt, format_args!("{}", name))?,
+            None => fmt.write_str("null")?,
+        }
+
+        fmt.write_str(",\"filename\":")?;
+        match symbol.and_then(|s| s.filename_raw()) {
+            Some(bows) => {
+                let path = DisplayPath { bows, print_fmt, cwd: cwd.as_ref(), prefixes };
+                write_json_str(fmt, format_args!("{}", path))?
+            }
+            None => fmt.write_str("null")?,
+        }
+
+        fmt.write_str(",\"lineno\":")?;
+        match symbol.and_then(|s| s.lineno()) {
+            Some(line) => write!(fmt, "{}", line)?,
+            None => fmt.write_str("null")?,
+        }
+
+        write!(fmt, ",\"address\":\"{:?}\"}}", ip)
+    })?;
+    fmt.write_str("\n]\n")
+}
+
+/// Prints the demangled names of the frames of the current backtrace on a
+/// single line, outermost first, like `main <- foo <- core::panicking::panic`.
+unsafe fn _print_compact_fmt(
+    fmt: &mut fmt::Formatter<'_>,
+    source: &FrameSource,
+    print_fmt: PrintFmt,
+) -> fmt::Result {
+    // The stack is walked innermost first, so the names are collected before
+    // printing them backwards.
+    let mut names = Vec::new();
+    trace_frames(source, print_fmt, false, &mut |traced| {
+        names.push(match traced.symbol.and_then(|s| s.name()) {
+            Some(name) => format!("{:#}", name),
+            None => "<unknown>".to_string(),
+        });
+        Ok(())
+    })?;
+    for (i, name) in names.iter().rev().enumerate() {
+        if i > 0 {
+            fmt.write_str(" <- ")?;
+        }
+        fmt.write_str(name)?;
+    }
+    fmt.write_str("\n")
+}
+
+/// Prints the instruction pointers of the frames of `source`, one per line, in
+/// the format `addr2line` reads them.
+///
+/// None of the frames are resolved, so short backtraces are only trimmed at
+/// the sentinels known by address, see `add_short_backtrace_sentinel`, and at
+/// the innermost guard of the current thread, see `push_short_backtrace_guard`.
+/// The frame limit still applies.
+unsafe fn _print_addrs_fmt(
+    fmt: &mut fmt::Formatter<'_>,
+    source: &FrameSource,
+    print_fmt: PrintFmt,
+) -> fmt::Result {
+    let short = print_fmt == PrintFmt::Short;
+    let trim = is_trimmed(print_fmt);
+    let mut sentinels = if trim { short_backtrace_sentinels() } else { Vec::new() };
+    match *source {
+        FrameSource::Captured(..) => {}
+        _ if trim => sentinels.extend(short_backtrace_guard()),
+        _ => {}
+    }
+    let limit = frame_limit(print_fmt);
+    let mut printed = 0;
+    let mut res = Ok(());
+    let mut frame = |ip: usize, function: Option<usize>| {
+        if function.map_or(false, |addr| sentinels.contains(&addr)) {
+            return false;
+        }
+        if short && ip == 0 {
+            return true;
+        }
+        if limit.map_or(false, |limit| printed >= limit) {
+            return false;
+        }
+        printed += 1;
+        res = writeln!(fmt, "{:#x}", ip);
+        res.is_ok()
+    };
+    match *source {
+        FrameSource::Current => backtrace_rs::trace_unsynchronized(|f| {
+            let function = Some(f.symbol_address() as usize).filter(|&addr| addr != 0);
+            frame(f.ip() as usize, function)
+        }),
+        FrameSource::Captured(ref ips, _) => {
+            for &ip in ips {
+                if !frame(ip, None) {
+                    break;
+                }
+            }
+        }
+        #[cfg(all(test, not(feature = "backtrace-minimal")))]
+        FrameSource::Synthetic(ref frames) => {
+            for f in frames {
+                if !frame(f.ip, f.addr) {
+                    break;
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Writes the escape sequence `code` if `color` is enabled.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn paint(fmt: &mut fmt::Formatter<'_>, color: bool, code: &str) -> fmt::Result {
+    if color { fmt.write_str(code) } else { Ok(()) }
+}
+
+/// Prints frames in the same layout as `backtrace_rs::BacktraceFmt`, with the
+/// decorations this module knows about layered on top.
+#[cfg(not(feature = "backtrace-minimal"))]
+struct FrameFmt<'a, 'b> {
+    fmt: &'a mut fmt::Formatter<'b>,
+    print_fmt: PrintFmt,
+    color: bool,
+    cwd: Option<&'a PathBuf>,
+    prefixes: &'a [PathBuf],
+    frame_index: usize,
+    /// Whether to print the line of source code under each frame, see
+    /// `Flag::Source`.
+    source: bool,
+    /// The crate of the previous symbol in full backtraces, used to print a
+    /// separator whenever it changes.
+    last_crate: Option<String>,
+    /// The index of the frame the last symbol printed belongs to, so that only
+    /// the first symbol of a frame is numbered.
+    numbered: Option<usize>,
+    /// Whether the next symbol printed is the one a panic originated from, see
+    /// `PanicOrigin`.
+    origin: bool,
+    /// Which symbol names are demangled, see `demangle_style`.
+    #[cfg(not(feature = "backtrace-minimal"))]
+    demangle: Demangle,
+    /// Whether the next symbol printed is the one unwinding stops at, see
+    /// `CatchBoundary`.
+    caught: bool,
+    /// What to print after each frame, see `frame_delimiter`.
+    delimiter: Option<&'static str>,
+    /// Whether to print the instruction pointer of each frame beneath it, see
+    /// `Flag::Regs`.
+    regs: bool,
+    /// Whether to mark the frames of the user's own code, see `is_user_file`.
+    classify: bool,
+    /// Whether to print frames without their locations, see `symbols_enabled`.
+    symbols_only: bool,
+    /// Whether to indent frames by their index, see `Flag::Tree`.
+    tree: bool,
+    /// The column to print locations at, on the line of their symbol, see
+    /// `align_column`.
+    align: Option<usize>,
+}
+
+#[cfg(not(feature = "backtrace-minimal"))]
+impl<'a, 'b> FrameFmt<'a, 'b> {
+    fn new(
+        fmt: &'a mut fmt::Formatter<'b>,
+        print_fmt: PrintFmt,
+        color: bool,
+        cwd: Option<&'a PathBuf>,
+        prefixes: &'a [PathBuf],
+    ) -> FrameFmt<'a, 'b> {
+        FrameFmt {
+            fmt,
+            print_fmt,
+            color,
+            cwd,
+            prefixes,
+            frame_index: 0,
+            source: false,
+            last_crate: None,
+            numbered: None,
+            origin: false,
+            #[cfg(not(feature = "backtrace-minimal"))]
+            demangle: Demangle::Auto,
+            caught: false,
+            delimiter: None,
+            regs: false,
+            classify: false,
+            symbols_only: false,
+            tree: false,
+            align: None,
+        }
+    }
+
+    /// Hands the frame to the formatter installed through
+    /// `std::backtrace::set_frame_formatter`, if there is one.
+    fn print_custom(
+        &mut self,
+        frame_ip: *mut c_void,
+        symbol_name: &Option<SymbolName<'_>>,
+        filename: &Option<BytesOrWideString<'_>>,
+        lineno: Option<u32>,
+    ) -> Option<fmt::Result> {
+        let name = symbol_name.as_ref().map(|name| format!("{:#}", name));
+        let file = filename.as_ref().map(|bows| {
+            bows_to_path(match *bows {
+                BytesOrWideString::Bytes(b) => BytesOrWideString::Bytes(b),
+                BytesOrWideString::Wide(w) => BytesOrWideString::Wide(w),
+            })
+        });
+        crate::backtrace::format_frame(
+            self.fmt,
+            frame_ip,
+            name.as_ref().map(|name| &name[..]),
+            file.as_ref().map(|file| &**file),
+            lineno,
+        )
+    }
+
+    /// Prints a symbol of the frame at `frame_ip`.
+    ///
+    /// Symbols of functions which were `inlined` into the frame are annotated
+    /// as such. Like in `backtrace`, only the first symbol printed for a frame
+    /// gets its index, the ones following it are indented instead.
+    ///
+    /// Full backtraces also print the offset of `frame_ip` into the symbol if
+    /// `symbol_addr` is known.
+    fn print_raw(
+        &mut self,
+        frame_ip: *mut c_void,
+        inlined: bool,
+        symbol_name: Option<SymbolName<'_>>,
+        symbol_addr: Option<*mut c_void>,
+        filename: Option<BytesOrWideString<'_>>,
+        lineno: Option<u32>,
+    ) -> fmt::Result {
+        if let Some(res) = self.print_custom(frame_ip, &symbol_name, &filename, lineno) {
+            return res;
+        }
+        let index = Some(self.frame_index);
+        let first = mem::replace(&mut self.numbered, index) != index;
+
+        // Fuchsia is unable to symbolize in process, so frames are emitted as
+        // markup for the offline symbolizer instead.
+        if cfg!(target_os = "fuchsia") {
+            if first {
+                writeln!(self.fmt, "{{{{{{bt:{}:{:?}}}}}}}", self.frame_index, frame_ip)?;
+            }
+            return Ok(());
+        }
+
+        // No need to print "null" frames, it basically just means that the
+        // system backtrace was a bit eager to trace back super far.
+        if self.print_fmt == PrintFmt::Short && frame_ip.is_null() {
+            return Ok(());
+        }
+        let corrupt = is_corrupt_frame(frame_ip, &symbol_name);
+
+        // SGX enclaves don't symbolize either, print the offset from the
+        // image base instead so the address can be mapped back later.
+        #[cfg(all(target_vendor = "fortanix", target_env = "sgx"))]
+        let frame_ip = {
+            let image_base = crate::sys::abi::mem::image_base();
+            (frame_ip as usize).wrapping_sub(image_base as usize) as *mut c_void
+        };
+
+        if self.symbols_only {
+            let (addr, name) = (symbol_addr, symbol_name);
+            return self.print_symbol_only(frame_ip, first, inlined, name, addr, corrupt);
+        }
+
+        if self.print_fmt == PrintFmt::Full {
+            let krate = symbol_name.as_ref().and_then(crate_name);
+            let changed = krate.as_ref().filter(|&k| self.last_crate.as_ref() != Some(k));
+            if let Some(name) = changed {
+                self.print_note(format_args!("--- in crate `{}` ---", name))?;
+            }
+            self.last_crate = krate;
+        }
+
+        // Frames of the user's own code are marked with a `>` in front of
+        // their index, while the names of the others are dimmed. Frames of the
+        // standard library never count as the user's, wherever its sources
+        // were built from.
+        let user = match filename {
+            Some(ref file) if self.classify => {
+                let file = match *file {
+                    BytesOrWideString::Bytes(bytes) => BytesOrWideString::Bytes(bytes),
+                    BytesOrWideString::Wide(wide) => BytesOrWideString::Wide(wide),
+                };
+                let std = symbol_name.as_ref().map_or(false, is_std_symbol);
+                Some(!std && is_user_file(&bows_to_path(file), self.cwd))
+            }
+            _ => None,
+        };
+        let marker = if user == Some(true) { ">" } else { " " };
+
+        // The width of the line so far is kept track of to align the location
+        // to the configured column, colors don't take up any.
+        let mut col = self.tree_indent();
+        let indent = if first { "" } else { "  " };
+        self.print_tree_indent()?;
+        if first {
+            if user == Some(true) {
+                self.fmt.write_str(marker)?;
+            }
+            let index = match user {
+                Some(true) => format!("{:3}:", self.frame_index),
+                _ => format!("{:4}:", self.frame_index),
+            };
+            paint(self.fmt, self.color, DIM)?;
+            self.fmt.write_str(&index)?;
+            paint(self.fmt, self.color, RESET)?;
+            self.fmt.write_str(" ")?;
+            col += (user == Some(true)) as usize + index.len() + 1;
+            if self.print_fmt == PrintFmt::Full {
+                write!(self.fmt, "{:1$?} ", frame_ip, HEX_WIDTH)?;
+                col += HEX_WIDTH + 1;
+                // Frames without a symbol are located through the module
+                // instead, which is only looked up for them as it costs a
+                // `dladdr` call per frame.
+                let module = if symbol_name.is_none() { module_offset(frame_ip) } else { None };
+                if let Some((module, offset)) = module {
+                    let module = format!("({}+{:#x}) ", module, offset);
+                    self.fmt.write_str(&module)?;
+                    col += module.chars().count();
+                }
+                self.fmt.write_str("- ")?;
+                col += 2;
+            }
+        } else {
+            self.fmt.write_str(marker)?;
+            self.fmt.write_str("     ")?;
+            col += 6;
+            if self.print_fmt == PrintFmt::Full {
+                write!(self.fmt, "{:1$}", "", HEX_WIDTH + 3)?;
+                col += HEX_WIDTH + 3;
+            }
+            self.fmt.write_str(indent)?;
+            col += indent.len();
+        }
+
+        let name = match symbol_name {
+            Some(name) => {
+                let name = if !self.demangles(&name) {
+                    String::from_utf8_lossy(name.as_bytes()).into_owned()
+                } else if self.print_fmt == PrintFmt::Short {
+                    format!("{:#}", name)
+                } else {
+                    format!("{}", name)
+                };
+                match symbol_width() {
+                    Some(width) => truncate_symbol(&name, width).into_owned(),
+                    None => name,
+                }
+            }
+            None if corrupt => "<corrupt frame>".to_string(),
+            None => "<unknown>".to_string(),
+        };
+        paint(self.fmt, self.color, if user == Some(false) { DIM } else { BOLD })?;
+        self.fmt.write_str(&name)?;
+        paint(self.fmt, self.color, RESET)?;
+        col += name.chars().count();
+        if self.print_fmt == PrintFmt::Full {
+            if let Some(addr) = symbol_addr {
+                let offset = (frame_ip as usize).checked_sub(addr as usize);
+                if let Some(offset) = offset {
+                    let offset = format!("+{:#x}", offset);
+                    self.fmt.write_str(&offset)?;
+                    col += offset.len();
+                }
+            }
+        }
+        if inlined {
+            self.fmt.write_str(" (inlined)")?;
+            col += " (inlined)".len();
+        }
+        if mem::replace(&mut self.origin, false) {
+            paint(self.fmt, self.color, YELLOW)?;
+            self.fmt.write_str(" <- panic originated here")?;
+            paint(self.fmt, self.color, RESET)?;
+            col += " <- panic originated here".len();
+        }
+        if mem::replace(&mut self.caught, false) {
+            paint(self.fmt, self.color, YELLOW)?;
+            self.fmt.write_str(" <- caught here (catch_unwind)")?;
+            paint(self.fmt, self.color, RESET)?;
+            col += " <- caught here (catch_unwind)".len();
+        }
+
+        // Locations are put on the line of their symbol if aligned, at least
+        // a space after it.
+        let aligned = match (self.align, &filename, lineno) {
+            (Some(align), Some(_), Some(_)) => Some(align),
+            _ => None,
+        };
+        match aligned {
+            Some(align) => {
+                write!(self.fmt, "{:1$}", "", align.saturating_sub(col).max(1))?;
+            }
+            None => self.fmt.write_str("\n")?,
+        }
+
+        if let (Some(file), Some(line)) = (filename, lineno) {
+            if aligned.is_none() {
+                self.print_tree_indent()?;
+                if self.print_fmt == PrintFmt::Full {
+                    write!(self.fmt, "{:1$}", "", HEX_WIDTH)?;
+                }
+                write!(self.fmt, "             {}", indent)?;
+            }
+            self.fmt.write_str("at ")?;
+            paint(self.fmt, self.color, CYAN)?;
+            // `output_filename` takes the filename by value, so the path of
+            // the source is looked up beforehand.
+            let source_path = if self.source {
+                Some(bows_to_path(match file {
+                    BytesOrWideString::Bytes(bytes) => BytesOrWideString::Bytes(bytes),
+                    BytesOrWideString::Wide(wide) => BytesOrWideString::Wide(wide),
+                }))
+            } else {
+                None
+            };
+            output_filename(self.fmt, file, self.print_fmt, self.cwd, self.prefixes)?;
+            paint(self.fmt, self.color, RESET)?;
+            writeln!(self.fmt, ":{}", line)?;
+            if let Some(path) = source_path {
+                self.print_source_line(&path, line)?;
+            }
+        }
+        if !inlined {
+            if self.regs {
+                self.print_regs(frame_ip)?;
+            }
+            self.print_delimiter()?;
+        }
+        Ok(())
+    }
+
+    /// Prints a frame as its symbol, the offset into it and the offset into
+    /// the module containing it, like `foo::bar+0x1c (foo+0x4f2c)`.
+    ///
+    /// Paths are left out, so the output neither depends on nor reveals the
+    /// source tree the binary was built from, while the offsets still allow
+    /// symbolicating it with the binary at hand.
+    fn print_symbol_only(
+        &mut self,
+        frame_ip: *mut c_void,
+        first: bool,
+        inlined: bool,
+        symbol_name: Option<SymbolName<'_>>,
+        symbol_addr: Option<*mut c_void>,
+        corrupt: bool,
+    ) -> fmt::Result {
+        self.print_tree_indent()?;
+        if !first {
+            self.fmt.write_str("        ")?;
+        } else {
+            paint(self.fmt, self.color, DIM)?;
+            write!(self.fmt, "{:4}:", self.frame_index)?;
+            paint(self.fmt, self.color, RESET)?;
+            self.fmt.write_str(" ")?;
+        }
+        paint(self.fmt, self.color, BOLD)?;
+        match symbol_name {
+            Some(name) if !self.demangles(&name) => {
+                self.fmt.write_str(&String::from_utf8_lossy(name.as_bytes()))?
+            }
+            Some(name) => write!(self.fmt, "{}", name)?,
+            None if corrupt => self.fmt.write_str("<corrupt frame>")?,
+            None => write!(self.fmt, "{:?}", frame_ip)?,
+        }
+        paint(self.fmt, self.color, RESET)?;
+        let offset = symbol_addr.and_then(|addr| (frame_ip as usize).checked_sub(addr as usize));
+        if let Some(offset) = offset {
+            write!(self.fmt, "+{:#x}", offset)?;
+        }
+        if let Some((module, offset)) = module_offset(frame_ip) {
+            write!(self.fmt, " ({}+{:#x})", module, offset)?;
+        }
+        if inlined {
+            self.fmt.write_str(" (inlined)")?;
+        }
+        self.fmt.write_str("\n")?;
+        if !inlined {
+            self.print_delimiter()?;
+        }
+        Ok(())
+    }
+
+    /// Starts a line of the current frame indented by its index, two spaces
+    /// per frame up to `MAX_TREE_DEPTH` of them, if enabled.
+    fn print_tree_indent(&mut self) -> fmt::Result {
+        let indent = self.tree_indent();
+        if indent > 0 {
+            write!(self.fmt, "{:1$}", "", indent)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the width of the indentation of `print_tree_indent`.
+    fn tree_indent(&self) -> usize {
+        if self.tree { 2 * self.frame_index.min(MAX_TREE_DEPTH) } else { 0 }
+    }
+
+    /// Prints the instruction pointer of the frame at `frame_ip` beneath it,
+    /// which is the only register printed.
+    fn print_regs(&mut self, frame_ip: *mut c_void) -> fmt::Result {
+        self.print_tree_indent()?;
+        write!(self.fmt, "{:1$}", "", HEX_WIDTH)?;
+        paint(self.fmt, self.color, DIM)?;
+        write!(self.fmt, "             ip={:1$?}", frame_ip, HEX_WIDTH)?;
+        paint(self.fmt, self.color, RESET)?;
+        writeln!(self.fmt)
+    }
+
+    /// Ends the output of a frame with the delimiter configured through
+    /// `RUST_BACKTRACE_DELIM`, if any.
+    fn print_delimiter(&mut self) -> fmt::Result {
+        match self.delimiter {
+            Some(delimiter) => self.fmt.write_str(delimiter),
+            None => Ok(()),
+        }
+    }
+
+    /// Prints line `line` of the file at `path` beneath the location of a
+    /// frame, or nothing if the file can't be read or is too short.
+    fn print_source_line(&mut self, path: &Path, line: u32) -> fmt::Result {
+        use crate::fs::File;
+        use crate::io::BufReader;
+
+        let source = match line.checked_sub(1) {
+            Some(n) => File::open(path)
+                .ok()
+                .and_then(|file| BufReader::new(file).lines().nth(n as usize))
+                .and_then(|line| line.ok()),
+            None => None,
+        };
+        if let Some(source) = source {
+            self.print_tree_indent()?;
+            if self.print_fmt == PrintFmt::Full {
+                write!(self.fmt, "{:1$}", "", HEX_WIDTH)?;
+            }
+            paint(self.fmt, self.color, DIM)?;
+            write!(self.fmt, "                 {}", source.trim())?;
+            paint(self.fmt, self.color, RESET)?;
+            self.fmt.write_str("\n")?;
+        }
+        Ok(())
+    }
+
+    /// Prints a marker for the `count` frames of the runtime which short
+    /// backtraces leave out at the end, so their frames can be matched up with
+    /// the ones of full backtraces.
+    #[cfg(not(feature = "backtrace-minimal"))]
+    fn print_trimmed(&mut self, count: usize) -> fmt::Result {
+        match count {
+            1 => self.print_note(format_args!("... 1 runtime frame hidden ...")),
+            n => self.print_note(format_args!("... {} runtime frames hidden ...", n)),
+        }
+    }
+
+    /// Prints a marker for `count` frames which were filtered out.
+    #[cfg(not(feature = "backtrace-minimal"))]
+    fn print_hidden(&mut self, count: usize) -> fmt::Result {
+        match count {
+            1 => self.print_note(format_args!("... 1 frame hidden ...")),
+            n => self.print_note(format_args!("... {} frames hidden ...", n)),
+        }
+    }
+
+    /// Prints the frame at `frame_ip` by its address, as it wasn't resolved to
+    /// save time.
+    #[cfg(not(feature = "backtrace-minimal"))]
+    fn print_unresolved(&mut self, frame_ip: *mut c_void) -> fmt::Result {
+        if let Some(res) = self.print_custom(frame_ip, &None, &None, None) {
+            return res;
+        }
+        paint(self.fmt, self.color, DIM)?;
+        write!(self.fmt, "{:4}:", self.frame_index)?;
+        paint(self.fmt, self.color, RESET)?;
+        writeln!(self.fmt, " {:1$?} - <unresolved>", frame_ip, HEX_WIDTH)?;
+        self.print_delimiter()
+    }
+
+    /// Prints a marker for the previous frame occurring `count` times in a
+    /// row, such as in a deep recursion.
+    #[cfg(not(feature = "backtrace-minimal"))]
+    fn print_repeated(&mut self, count: usize) -> fmt::Result {
+        self.print_note(format_args!("(repeated {} times)", count))
+    }
+
+    /// Returns whether `name` is printed demangled, see `demangle_style`.
+    #[cfg(not(feature = "backtrace-minimal"))]
+    fn demangles(&self, name: &SymbolName<'_>) -> bool {
+        self.demangle.applies_to(name.as_bytes())
+    }
+
+    /// With the `backtrace-minimal` feature, names are always demangled.
+    #[cfg(feature = "backtrace-minimal")]
+    fn demangles(&self, _name: &SymbolName<'_>) -> bool {
+        true
+    }
+
+    /// Prints `args` dimmed, aligned with the symbol names of the frames.
+    fn print_note(&mut self, args: fmt::Arguments<'_>) -> fmt::Result {
+        self.print_tree_indent()?;
+        self.fmt.write_str("      ")?;
+        if self.print_fmt == PrintFmt::Full {
+            write!(self.fmt, "{:1$}", "", HEX_WIDTH + 3)?;
+        }
+        paint(self.fmt, self.color, DIM)?;
+        self.fmt.write_fmt(args)?;
+        paint(self.fmt, self.color, RESET)?;
+        self.fmt.write_str("\n")
+    }
+
+    /// Prints a line like `--- spawned at ---` between the frames of two
+    /// backtraces shown as one.
+    fn print_separator(&mut self, label: &str) -> fmt::Result {
+        self.print_note(format_args!("--- {} ---", label))
+    }
+
+}
+
+/// A frame handed out by `trace_frames`.
+struct TracedFrame<'a> {
+    /// Index of the frame among the ones handed out so far.
+    #[cfg(not(feature = "backtrace-minimal"))]
+    idx: usize,
+    ip: *mut c_void,
+    /// The symbol being printed, or `None` if the frame couldn't be resolved.
+    symbol: Option<&'a ResolvedSymbol>,
+    /// Whether the symbol is of a function inlined into the frame, that is
+    /// whether another symbol of the same frame follows it.
+    inlined: bool,
+    /// Whether the frame wasn't resolved because the time budget for doing so
+    /// ran out, see `resolve_budget`.
+    #[cfg(not(feature = "backtrace-minimal"))]
+    unresolved: bool,
+    /// Number of frames filtered out since the previous one handed out.
+    #[cfg(not(feature = "backtrace-minimal"))]
+    hidden: usize,
+    /// Number of times the previous frame handed out was repeated right after
+    /// itself, see the `collapse_recursion` argument of `trace_frames`.
+    #[cfg(not(feature = "backtrace-minimal"))]
+    repeats: usize,
+    /// If this is the first frame of a backtrace chained on to the previous
+    /// one, the number of frames trimmed off the end of that, see
+    /// `FrameWalk::chain`.
+    #[cfg(not(feature = "backtrace-minimal"))]
+    spawned: Option<usize>,
+}
+
+/// Summary of what `trace_frames` left out of a backtrace.
+struct TraceStats {
+    /// Whether any frames were left out at the end, either because the frame
+    /// limit was reached or because the trace was trimmed at
+    /// `__rust_begin_short_backtrace`.
+    truncated: bool,
+    /// The frame limit, if the trace was truncated because it was reached.
+    capped: Option<usize>,
+    /// Number of frames filtered out after the last one handed out.
+    hidden: usize,
+    /// Number of times the last frame handed out was repeated at the end.
+    repeats: usize,
+    /// Number of frames on the stack, including the ones left out.
+    total: usize,
+    /// Number of frames handed out.
+    shown: usize,
+    /// Number of frames left out because they belong to the runtime, from
+    /// `__rust_begin_short_backtrace` on.
+    trimmed: usize,
+    /// Number of symbols left out by the configured filters, in total.
+    filtered: usize,
+    /// Number of frames handed out without resolving them, see
+    /// `resolve_budget`.
+    unresolved: usize,
+    /// Number of frames handed out with the source location of at least one
+    /// of their symbols.
+    located: usize,
+    /// Whether any frame on the stack resolved to a named symbol, including
+    /// the ones left out.
+    resolved_any: bool,
+}
+
+/// Where the frames of a backtrace come from.
+enum FrameSource {
+    /// The stack of the current thread.
+    Current,
+    /// The instruction pointers of a stack captured earlier, innermost first,
+    /// and the indices of the ones at which the backtraces chained on to it
+    /// begin, see `std::backtrace::Backtrace::chain`.
+    Captured(Vec<usize>, Vec<usize>),
+    /// Made up frames, to test the formatting independently of the stack.
+    #[cfg(all(test, not(feature = "backtrace-minimal")))]
+    Synthetic(Vec<SyntheticFrame>),
+}
+
+/// A made up frame, see `FrameSource::Synthetic`.
+#[cfg(all(test, not(feature = "backtrace-minimal")))]
+#[derive(Clone)]
+struct SyntheticFrame {
+    ip: usize,
+    /// The address of the function the frame is in.
+    addr: Option<usize>,
+    name: Option<String>,
+    filename: Option<String>,
+    lineno: Option<u32>,
+    /// The names of functions inlined into the frame, innermost first.
+    inlined: Vec<String>,
+}
+
+#[cfg(all(test, not(feature = "backtrace-minimal")))]
+impl SyntheticFrame {
+    /// Returns the symbols the frame resolves to, where a frame without any
+    /// information about its symbol is taken as unresolved.
+    fn symbols(&self) -> Vec<ResolvedSymbol> {
+        if self.addr.is_none() && self.name.is_none() && self.filename.is_none() {
+            return Vec::new();
+        }
+        let filename =
+            || self.filename.as_ref().map(|f| BytesOrWide::Bytes(f.as_bytes().to_vec()));
+        let mut symbols: Vec<_> = self.inlined.iter().map(|name| ResolvedSymbol {
+            name: Some(name.as_bytes().to_vec()),
+            addr: self.addr,
+            filename: filename(),
+            lineno: self.lineno,
+        }).collect();
+        symbols.push(ResolvedSymbol {
+            name: self.name.as_ref().map(|n| n.as_bytes().to_vec()),
+            addr: self.addr,
+            filename: filename(),
+            lineno: self.lineno,
+        });
+        symbols
+    }
+}
+
+/// Formats `frames` like `print` would format the current stack, without
+/// colors.
+#[cfg(all(test, not(feature = "backtrace-minimal")))]
+fn format_synthetic(
+    frames: Vec<SyntheticFrame>,
+    print_fmt: PrintFmt,
+    style: OutputStyle,
+) -> String {
+    let mut buf = Vec::new();
+    unsafe {
+        _print(&mut buf, &FrameSource::Synthetic(frames), print_fmt, style, false, false).unwrap();
+    }
+    String::from_utf8(buf).unwrap()
+}
+
+/// Walks the frames of `source` and hands each frame which should be printed
+/// with the given format to `f`.
+///
+/// Once the frames to print are exhausted, the rest of the stack is still
+/// walked without resolving it to count its frames, see `TraceStats`.
+///
+/// The innermost frames requested through `RUST_BACKTRACE_SKIP` are dropped
+/// before they are resolved, and neither take up an index nor count towards
+/// the frame limit. Trimming at `__rust_begin_short_backtrace` only looks at
+/// the frames after them, so skipping past it prints the rest of the stack.
+///
+/// `f` is called once for every symbol of a frame, or once without a symbol if
+/// the frame couldn't be resolved. If `collapse_recursion` is set, short
+/// backtraces only hand out the first of consecutive frames resolving to the
+/// same symbol and location, and count the rest as its repeats.
+unsafe fn trace_frames(
+    source: &FrameSource,
+    print_fmt: PrintFmt,
+    collapse_recursion: bool,
+    f: &mut dyn FnMut(TracedFrame<'_>) -> fmt::Result,
+) -> Result<TraceStats, fmt::Error> {
+    let mut walk = FrameWalk::new(print_fmt, collapse_recursion);
+    // The guards only tell about the stack of the current thread.
+    match *source {
+        FrameSource::Captured(..) => {}
+        _ if walk.trim => walk.sentinels.extend(short_backtrace_guard()),
+        _ => {}
+    }
+    match *source {
+        FrameSource::Current => {
+            // See `print` for why the stack isn't walked in tests.
+            if !cfg!(test) {
+                backtrace_rs::trace_unsynchronized(|frame| {
+                    let function = Some(frame.symbol_address() as usize).filter(|&a| a != 0);
+                    walk.frame(frame.ip(), function, || resolve_frame(frame), f)
+                });
+            }
+        }
+        FrameSource::Captured(ref ips, ref spawned_at) => {
+            for (i, &ip) in ips.iter().enumerate() {
+                if spawned_at.contains(&i) {
+                    walk.chain();
+                }
+                if !walk.frame(ip as *mut c_void, None, || resolve_address(ip), f) {
+                    break;
+                }
+            }
+        }
+        #[cfg(all(test, not(feature = "backtrace-minimal")))]
+        FrameSource::Synthetic(ref frames) => {
+            for frame in frames {
+                if !walk.frame(frame.ip as *mut c_void, frame.addr, || frame.symbols(), f) {
+                    break;
+                }
+            }
+        }
+    }
+    walk.finish()
+}
+
+/// The state of `trace_frames` while walking a stack.
+struct FrameWalk {
+    print_fmt: PrintFmt,
+    limit: Option<usize>,
+    filters: &'static [String],
+    allowlist: &'static [String],
+    /// Whether the backtrace ends at `__rust_begin_short_backtrace` and the
+    /// registered sentinels, see `is_trimmed`.
+    trim: bool,
+    sentinels: Vec<usize>,
+    hide_std: bool,
+    collapse_recursion: bool,
+    skip: usize,
+    /// How much time may still be spent resolving frames.
+    budget: Option<Duration>,
+    /// The last symbol handed out, to recognize recursion.
+    last: Option<ResolvedSymbol>,
+    /// The index of the next frame handed out.
+    idx: usize,
+    stats: TraceStats,
+    /// Once done, the rest of the stack is only walked to count its frames.
+    done: bool,
+    trimming: bool,
+    /// The number of frames trimmed off the previous backtrace, until the
+    /// first frame of the one chained on to it is handed out.
+    #[cfg(not(feature = "backtrace-minimal"))]
+    chained: Option<usize>,
+    /// The number of frames trimmed off the backtraces before the current one.
+    #[cfg(not(feature = "backtrace-minimal"))]
+    trimmed_before: usize,
+    res: fmt::Result,
+}
+
+impl FrameWalk {
+    fn new(print_fmt: PrintFmt, collapse_recursion: bool) -> FrameWalk {
+        FrameWalk {
+            print_fmt,
+            limit: frame_limit(print_fmt),
+            filters: symbol_filters(print_fmt),
+            allowlist: crate_allowlist(),
+            trim: is_trimmed(print_fmt),
+            sentinels: if is_trimmed(print_fmt) { short_backtrace_sentinels() } else { Vec::new() },
+            hide_std: print_fmt == PrintFmt::Full && std_frames_hidden(),
+            collapse_recursion: collapse_recursion && print_fmt == PrintFmt::Short,
+            skip: frame_skip(),
+            budget: resolve_budget(),
+            last: None,
+            idx: 0,
+            stats: TraceStats {
+                truncated: false,
+                capped: None,
+                hidden: 0,
+                repeats: 0,
+                total: 0,
+                shown: 0,
+                trimmed: 0,
+                filtered: 0,
+                unresolved: 0,
+                located: 0,
+                resolved_any: false,
+            },
+            done: false,
+            trimming: false,
+            #[cfg(not(feature = "backtrace-minimal"))]
+            chained: None,
+            #[cfg(not(feature = "backtrace-minimal"))]
+            trimmed_before: 0,
+            res: Ok(()),
+        }
+    }
+
+    /// Processes the frame at `ip`, whose symbols are only resolved if needed,
+    /// and returns whether to keep walking the stack.
+    ///
+    /// `function` is the start address of the function the frame is in, if it
+    /// is known without resolving the frame.
+    fn frame(
+        &mut self,
+        ip: *mut c_void,
+        function: Option<usize>,
+        resolve: impl FnOnce() -> Vec<ResolvedSymbol>,
+        f: &mut dyn FnMut(TracedFrame<'_>) -> fmt::Result,
+    ) -> bool {
+        let stats = &mut self.stats;
+        stats.total += 1;
+        if self.done {
+            if self.trimming {
+                stats.trimmed += 1;
+            }
+            return true;
+        }
+        if self.skip > 0 {
+            self.skip -= 1;
+            return true;
+        }
+        // Short backtraces don't print "null" frames, see `FrameFmt::print_raw`,
+        // so they don't take up an index either.
+        if self.print_fmt == PrintFmt::Short && ip.is_null() {
+            return true;
+        }
+        if let Some(limit) = self.limit {
+            if self.idx >= limit {
+                stats.truncated = true;
+                stats.capped = Some(limit);
+                self.done = true;
+                return true;
+            }
+        }
+
+        // The budget is only checked between frames, resolving a single frame
+        // can't be interrupted.
+        let unresolved = self.budget == Some(Duration::from_secs(0));
+        let symbols = match self.budget {
+            _ if unresolved => {
+                stats.unresolved += 1;
+                Vec::new()
+            }
+            Some(budget) => {
+                let start = Instant::now();
+                let symbols = resolve();
+                self.budget = Some(budget.checked_sub(start.elapsed()).unwrap_or_default());
+                symbols
+            }
+            None => resolve(),
+        };
+        if symbols.iter().any(|symbol| symbol.name().is_some()) {
+            stats.resolved_any = true;
+        }
+        let sentinels = &self.sentinels;
+        let mut stop = function.map_or(false, |addr| sentinels.contains(&addr));
+        // The symbols of a frame are picked before handing any out, so that
+        // the last one shown can be told apart from the inlined ones.
+        let mut kept = Vec::new();
+        for symbol in &symbols {
+            if stop {
+                break;
+            }
+            if self.trim {
+                let sentinel = symbol.addr().map_or(false, |a| sentinels.contains(&(a as usize)));
+                let end = symbol.name().map_or(false, |name| is_short_backtrace_end(&name));
+                if sentinel || end {
+                    stop = true;
+                    break;
+                }
+            }
+            if let Some(name) = symbol.name() {
+                if self.hide_std && is_std_symbol(&name) {
+                    stats.hidden += 1;
+                    stats.filtered += 1;
+                    continue;
+                }
+                if !self.filters.is_empty() {
+                    let name = name.to_string();
+                    if self.filters.iter().any(|filter| name.contains(&filter[..])) {
+                        stats.hidden += 1;
+                        stats.filtered += 1;
+                        continue;
+                    }
+                }
+                if !self.allowlist.is_empty() {
+                    let demangled = format!("{:#}", name);
+                    let path = demangled.trim_start_matches('<');
+                    if !self.allowlist.iter().any(|prefix| path.starts_with(&prefix[..])) {
+                        stats.hidden += 1;
+                        stats.filtered += 1;
+                        continue;
+                    }
+                }
+            }
+
+            if self.collapse_recursion {
+                // Frames only count as a repetition if nothing was filtered
+                // out in between.
+                if stats.hidden == 0 && self.last.as_ref() == Some(symbol) {
+                    stats.repeats += 1;
+                    continue;
+                }
+                self.last = Some(symbol.clone());
+            }
+
+            kept.push(TracedFrame {
+                #[cfg(not(feature = "backtrace-minimal"))]
+                idx: self.idx,
+                ip,
+                symbol: Some(symbol),
+                inlined: false,
+                #[cfg(not(feature = "backtrace-minimal"))]
+                unresolved: false,
+                #[cfg(not(feature = "backtrace-minimal"))]
+                hidden: mem::replace(&mut stats.hidden, 0),
+                #[cfg(not(feature = "backtrace-minimal"))]
+                repeats: mem::replace(&mut stats.repeats, 0),
+                #[cfg(not(feature = "backtrace-minimal"))]
+                spawned: self.chained.take(),
+            });
+        }
+        let mut shown = !kept.is_empty();
+        if kept.iter().any(|traced| traced.symbol.map_or(false, |s| s.filename_raw().is_some())) {
+            stats.located += 1;
+        }
+        let count = kept.len();
+        for (i, mut traced) in kept.into_iter().enumerate() {
+            if self.res.is_err() {
+                break;
+            }
+            traced.inlined = i + 1 < count;
+            self.res = f(traced);
+        }
+        if stop {
+            // A frame with inlined symbols before the sentinel one is shown,
+            // and doesn't count as trimmed as well.
+            if shown {
+                self.idx += 1;
+            } else {
+                stats.trimmed += 1;
+            }
+            stats.truncated = true;
+            self.done = true;
+            self.trimming = true;
+            return self.res.is_ok();
+        }
+        if symbols.is_empty() {
+            shown = true;
+            self.last = None;
+            self.res = f(TracedFrame {
+                #[cfg(not(feature = "backtrace-minimal"))]
+                idx: self.idx,
+                ip,
+                symbol: None,
+                inlined: false,
+                #[cfg(not(feature = "backtrace-minimal"))]
+                unresolved,
+                #[cfg(not(feature = "backtrace-minimal"))]
+                hidden: mem::replace(&mut stats.hidden, 0),
+                #[cfg(not(feature = "backtrace-minimal"))]
+                repeats: mem::replace(&mut stats.repeats, 0),
+                #[cfg(not(feature = "backtrace-minimal"))]
+                spawned: self.chained.take(),
+            });
+        }
+
+        // Frames which were filtered out entirely don't take up an index.
+        if shown {
+            self.idx += 1;
+        }
+        self.res.is_ok()
+    }
+
+    /// Starts the backtrace chained on to the one walked so far, whose frames
+    /// are trimmed separately. The frame limit still counts the frames of all
+    /// of them.
+    fn chain(&mut self) {
+        if self.trimming {
+            self.done = false;
+            self.trimming = false;
+        }
+        self.last = None;
+        #[cfg(not(feature = "backtrace-minimal"))]
+        {
+            self.chained = Some(self.stats.trimmed - self.trimmed_before);
+            self.trimmed_before = self.stats.trimmed;
+        }
+    }
+
+    fn finish(mut self) -> Result<TraceStats, fmt::Error> {
+        self.stats.shown = self.idx;
+        let stats = self.stats;
+        self.res.map(|()| stats)
+    }
+}
+
+/// An owned copy of a `BytesOrWideString`.
+#[derive(Clone, PartialEq, Eq)]
+pub enum BytesOrWide {
+    Bytes(Vec<u8>),
+    Wide(Vec<u16>),
+}
+
+impl BytesOrWide {
+    pub fn new(bows: BytesOrWideString<'_>) -> BytesOrWide {
+        match bows {
+            BytesOrWideString::Bytes(b) => BytesOrWide::Bytes(b.to_owned()),
+            BytesOrWideString::Wide(w) => BytesOrWide::Wide(w.to_owned()),
+        }
+    }
+
+    pub fn as_bows(&self) -> BytesOrWideString<'_> {
+        match self {
+            BytesOrWide::Bytes(b) => BytesOrWideString::Bytes(b),
+            BytesOrWide::Wide(w) => BytesOrWideString::Wide(w),
+        }
+    }
+
+    /// Returns the path this filename stands for, see `bows_to_path`.
+    pub fn to_path(&self) -> Cow<'_, Path> {
+        bows_to_path(self.as_bows())
+    }
+}
+
+/// An owned copy of the information resolved for a symbol.
+#[derive(Clone, PartialEq, Eq)]
+struct ResolvedSymbol {
+    name: Option<Vec<u8>>,
+    addr: Option<usize>,
+    filename: Option<BytesOrWide>,
+    lineno: Option<u32>,
+}
+
+impl ResolvedSymbol {
+    fn new(symbol: &backtrace_rs::Symbol) -> ResolvedSymbol {
+        ResolvedSymbol {
+            name: symbol.name().map(|n| n.as_bytes().to_vec()),
+            addr: symbol.addr().map(|a| a as usize),
+            filename: symbol.filename_raw().map(BytesOrWide::new),
+            lineno: symbol.lineno(),
+        }
+    }
+
+    fn name(&self) -> Option<SymbolName<'_>> {
+        self.name.as_ref().map(|n| SymbolName::new(n))
+    }
+
+    fn addr(&self) -> Option<*mut c_void> {
+        self.addr.map(|a| a as *mut c_void)
+    }
+
+    fn filename_raw(&self) -> Option<BytesOrWideString<'_>> {
+        self.filename.as_ref().map(BytesOrWide::as_bows)
+    }
+
+    fn lineno(&self) -> Option<u32> {
+        self.lineno
+    }
+}
+
+/// Max number of frames whose symbols are remembered by `resolve_cached`.
+const MAX_CACHED_FRAMES: usize = 1024;
+
+/// Resolves the symbols of `frame`.
+///
+/// Resolution is expensive and programs which keep panicking tend to do so
+/// from the same places, so the symbols are remembered by instruction pointer
+/// for the following backtraces. Once `MAX_CACHED_FRAMES` frames are cached,
+/// the oldest entries are replaced.
+unsafe fn resolve_frame(frame: &backtrace_rs::Frame) -> Vec<ResolvedSymbol> {
+    resolve_cached(frame.ip() as usize, || {
+        let mut symbols = Vec::new();
+        backtrace_rs::resolve_frame_unsynchronized(frame, |symbol| {
+            symbols.push(ResolvedSymbol::new(symbol));
+        });
+        symbols
+    })
+}
+
+/// Resolves the symbols of the frame at the instruction pointer `ip` of a
+/// stack captured earlier, see `resolve_frame`.
+unsafe fn resolve_address(ip: usize) -> Vec<ResolvedSymbol> {
+    resolve_cached(ip, || {
+        // Unlike `resolve_frame_unsynchronized`, this doesn't take into account
+        // that `ip` is a return address, which may already belong to the next
+        // line or even function. Look up the call instruction before it.
+        let mut symbols = Vec::new();
+        let addr = ip.saturating_sub(1) as *mut c_void;
+        backtrace_rs::resolve_unsynchronized(addr, |symbol| {
+            symbols.push(ResolvedSymbol::new(symbol));
+        });
+        symbols
+    })
+}
+
+/// Returns the symbols of the frame at `ip` resolved earlier, or resolves them
+/// with `resolve` and remembers them.
+unsafe fn resolve_cached(
+    ip: usize,
+    resolve: impl FnOnce() -> Vec<ResolvedSymbol>,
+) -> Vec<ResolvedSymbol> {
+    struct Cache {
+        entries: Vec<(usize, Vec<ResolvedSymbol>)>,
+        next: usize,
+    }
+
+    static LOCK: Mutex = Mutex::new();
+    static mut CACHE: Cache = Cache { entries: Vec::new(), next: 0 };
+
+    // See `print` for why the backtrace machinery is skipped in tests.
+    if cfg!(test) {
+        return resolve();
+    }
+
+    LOCK.lock();
+    let cached = CACHE.entries.iter().find(|e| e.0 == ip).map(|e| e.1.clone());
+    LOCK.unlock();
+    if let Some(symbols) = cached {
+        return symbols;
+    }
+
+    let symbols = resolve();
+    LOCK.lock();
+    if CACHE.entries.len() < MAX_CACHED_FRAMES {
+        CACHE.entries.push((ip, symbols.clone()));
+    } else {
+        let next = CACHE.next;
+        CACHE.entries[next] = (ip, symbols.clone());
+        CACHE.next = (next + 1) % MAX_CACHED_FRAMES;
+    }
+    LOCK.unlock();
+    symbols
+}
+
+/// Returns the file name of the module containing `ip` along with the offset
+/// of `ip` from the address the module is loaded at, which unlike `ip` itself
+/// doesn't change between runs.
+#[cfg(all(
+    not(feature = "backtrace-minimal"),
+    any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "solaris"
+    )
+))]
+fn module_offset(ip: *mut c_void) -> Option<(String, usize)> {
+    use crate::ffi::CStr;
+
+    unsafe {
+        let mut info: libc::Dl_info = mem::zeroed();
+        if libc::dladdr(ip as *const libc::c_void, &mut info) == 0
+            || info.dli_fbase.is_null()
+            || info.dli_fname.is_null()
+        {
+            return None;
+        }
+        let path = CStr::from_ptr(info.dli_fname).to_bytes();
+        let name = path.rsplit(|&b| b == b'/').next().unwrap_or(path);
+        let offset = (ip as usize).wrapping_sub(info.dli_fbase as usize);
+        Some((String::from_utf8_lossy(name).into_owned(), offset))
+    }
+}
+
+#[cfg(all(
+    not(feature = "backtrace-minimal"),
+    not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "solaris"
+    ))
+))]
+fn module_offset(_ip: *mut c_void) -> Option<(String, usize)> {
+    None
+}
+
+/// Returns whether the frame at `ip` which resolved to `symbol_name` can't be
+/// a frame of actual code, so it is printed as `<corrupt frame>`.
+///
+/// Such frames show up at the end of the stack or when walking a corrupted
+/// one. No code is mapped into the first page of memory, so unresolved
+/// addresses below `MIN_CODE_ADDR` are taken for bogus ones.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn is_corrupt_frame(ip: *mut c_void, symbol_name: &Option<SymbolName<'_>>) -> bool {
+    symbol_name.is_none() && (ip as usize) < MIN_CODE_ADDR
+}
+
+/// Returns the crate `name` is defined in, if its demangled form starts with
+/// one. Symbols like `<T as Trait>::method` aren't attributed to any crate.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn crate_name(name: &SymbolName<'_>) -> Option<String> {
+    let demangled = format!("{:#}", name);
+    let krate = &demangled[..demangled.find("::")?];
+    if krate.is_empty() || !krate.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+    Some(krate.to_string())
+}
+
+/// Returns whether `name` belongs to the standard library, including the
+/// implementations of its traits for its own types.
+fn is_std_symbol(name: &SymbolName<'_>) -> bool {
+    let demangled = format!("{:#}", name);
+    let path = demangled.trim_start_matches('<');
+    ["core::", "std::", "alloc::", "__rust_"].iter().any(|prefix| path.starts_with(prefix))
+}
+
+/// Returns whether `name` is the symbol short backtraces are trimmed at.
+///
+/// Frames are recognized by the address of their function first, see
+/// `short_backtrace_sentinels`. The name covers frames whose function address
+/// isn't known, as well as the functions of the same name in other crates like
+/// `test`. Only a path segment matching `SHORT_BACKTRACE_SENTINEL` exactly
+/// counts, so symbols which merely contain it don't.
+///
+/// The runtime's `lang_start` functions calling `main` end short backtraces
+/// too, in case `__rust_begin_short_backtrace` didn't make it into the stack,
+/// so the frames of the C runtime before them are never printed.
+fn is_short_backtrace_end(name: &SymbolName<'_>) -> bool {
+    let demangled = format!("{:#}", name);
+    demangled.starts_with("std::rt::lang_start")
+        || demangled.split("::").any(|segment| segment == SHORT_BACKTRACE_SENTINEL)
+}
+
+static SENTINEL_LOCK: Mutex = Mutex::new();
+static mut SENTINELS: Vec<usize> = Vec::new();
+
+/// Has short backtraces trimmed at the function starting at `addr` as well,
+/// like at `__rust_begin_short_backtrace`.
+pub fn add_short_backtrace_sentinel(addr: usize) {
+    unsafe {
+        SENTINEL_LOCK.lock();
+        if !SENTINELS.contains(&addr) {
+            SENTINELS.push(addr);
+        }
+        SENTINEL_LOCK.unlock();
+    }
+}
+
+/// Returns the addresses of the functions short backtraces are trimmed at: the
+/// frame every instance of `__rust_begin_short_backtrace` calls through, and
+/// the ones registered through `add_short_backtrace_sentinel`.
+fn short_backtrace_sentinels() -> Vec<usize> {
+    let mut sentinels = vec![__rust_begin_short_backtrace_frame as usize];
+    unsafe {
+        SENTINEL_LOCK.lock();
+        sentinels.extend_from_slice(&SENTINELS);
+        SENTINEL_LOCK.unlock();
+    }
+    sentinels
+}
+
+thread_local! {
+    static GUARDS: crate::cell::RefCell<Vec<usize>> = crate::cell::RefCell::new(Vec::new())
+}
+
+/// Has short backtraces of the current thread trimmed at the function starting
+/// at `addr` as well, until `pop_short_backtrace_guard` is called with the
+/// returned depth. Only the innermost of these functions is trimmed at.
+pub fn push_short_backtrace_guard(addr: usize) -> usize {
+    GUARDS
+        .try_with(|guards| {
+            let mut guards = guards.borrow_mut();
+            guards.push(addr);
+            guards.len() - 1
+        })
+        .unwrap_or(0)
+}
+
+/// Removes the guard pushed at `depth`, as well as any pushed after it.
+pub fn pop_short_backtrace_guard(depth: usize) {
+    let _ = GUARDS.try_with(|guards| guards.borrow_mut().truncate(depth));
+}
+
+/// Returns the address of the function pushed last through
+/// `push_short_backtrace_guard` on the current thread.
+fn short_backtrace_guard() -> Option<usize> {
+    GUARDS
+        .try_with(|guards| guards.borrow().last().cloned())
+        .unwrap_or(None)
+        .filter(|&addr| addr != 0)
+}
+
+/// Returns the start address of the function which called the one starting at
+/// `callee`, or 0 if it isn't known.
+///
+/// `callee` must be marked `#[inline(never)]`, so it has a frame of its own.
+pub fn caller_function(callee: usize) -> usize {
+    // See `print` for why this is skipped in tests.
+    if cfg!(test) {
+        return 0;
+    }
+
+    let mut found = false;
+    let mut caller = 0;
+    unsafe {
+        let _lock = lock();
+        backtrace_rs::trace_unsynchronized(|frame| {
+            let function = frame.symbol_address() as usize;
+            if found {
+                caller = function;
+                return false;
+            }
+            found = function == callee;
+            true
+        });
+    }
+    caller
+}
+
+/// Writes the output of `args` as a quoted and escaped JSON string.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn write_json_str(fmt: &mut fmt::Formatter<'_>, args: fmt::Arguments<'_>) -> fmt::Result {
+    struct JsonEscape<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+    impl fmt::Write for JsonEscape<'_, '_> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            for c in s.chars() {
+                match c {
+                    '"' => self.0.write_str("\\\"")?,
+                    '\\' => self.0.write_str("\\\\")?,
+                    '\n' => self.0.write_str("\\n")?,
+                    '\r' => self.0.write_str("\\r")?,
+                    '\t' => self.0.write_str("\\t")?,
+                    c if c < ' ' => write!(self.0, "\\u{:04x}", c as u32)?,
+                    c => fmt::Write::write_char(self.0, c)?,
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fmt.write_str("\"")?;
+    fmt::write(&mut JsonEscape(fmt), args)?;
+    fmt.write_str("\"")
+}
+
+/// Adapter to print a path through `output_filename` with `{}`.
+#[cfg(not(feature = "backtrace-minimal"))]
+struct DisplayPath<'a> {
+    bows: BytesOrWideString<'a>,
+    print_fmt: PrintFmt,
+    cwd: Option<&'a PathBuf>,
+    prefixes: &'a [PathBuf],
+}
+
+#[cfg(not(feature = "backtrace-minimal"))]
+impl fmt::Display for DisplayPath<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bows = match self.bows {
+            BytesOrWideString::Bytes(bytes) => BytesOrWideString::Bytes(bytes),
+            BytesOrWideString::Wide(wide) => BytesOrWideString::Wide(wide),
+        };
+        output_filename(fmt, bows, self.print_fmt, self.cwd, self.prefixes)
+    }
+}
+
+/// Fixed frame used to clean the backtrace with `RUST_BACKTRACE=1`.
+#[inline(never)]
+pub fn __rust_begin_short_backtrace<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T,
+    F: Send,
+    T: Send,
+{
+    // Every instance of this function calls `f` through the same frame, so
+    // that its address is known without registering each of them.
+    let mut f = Some(f);
+    let mut result = None;
+    __rust_begin_short_backtrace_frame(&mut || result = f.take().map(|f| f()));
+    result.unwrap()
+}
+
+/// The frame `__rust_begin_short_backtrace` calls `f` through, recognized by
+/// its address in short backtraces, see `short_backtrace_sentinels`.
+#[inline(never)]
+fn __rust_begin_short_backtrace_frame(f: &mut dyn FnMut()) {
+    f();
+    // Keep the call from being turned into a tail call, which would take this
+    // frame off the stack.
+    crate::sync::atomic::compiler_fence(SeqCst);
+}
+
+// Returns the maximum number of frames printed in the given format, or `None`
+// if the limit was lifted entirely by setting it to 0. Short backtraces are
+// limited through `RUST_BACKTRACE_LIMIT` and full ones, with a much higher
+// default, through `RUST_BACKTRACE_FULL_LIMIT`. Short backtraces which aren't
+// trimmed, see `is_trimmed`, are limited like full ones.
+fn frame_limit(print_fmt: PrintFmt) -> Option<usize> {
+    use crate::sync::atomic::AtomicUsize;
+
+    static LIMIT: AtomicUsize = AtomicUsize::new(0);
+    static FULL_LIMIT: AtomicUsize = AtomicUsize::new(0);
+    match print_fmt {
+        PrintFmt::Short if is_trimmed(print_fmt) => {
+            env_limit(&LIMIT, "RUST_BACKTRACE_LIMIT", MAX_NB_FRAMES)
+        }
+        _ => env_limit(&FULL_LIMIT, "RUST_BACKTRACE_FULL_LIMIT", MAX_NB_FRAMES_FULL),
+    }
+}
+
+/// Returns whether backtraces in the given format end at the runtime's
+/// frames, from `__rust_begin_short_backtrace`, a registered sentinel or the
+/// innermost guard of the current thread on.
+///
+/// Short backtraces do unless `RUST_BACKTRACE_NO_TRIM=1`, which keeps their
+/// layout while printing the whole stack like full ones.
+fn is_trimmed(print_fmt: PrintFmt) -> bool {
+    print_fmt == PrintFmt::Short && !env_flag(Flag::NoTrim)
+}
+
+// Returns the limit configured through the environment variable `key`, caching
+// it in `cache`.
+fn env_limit(cache: &crate::sync::atomic::AtomicUsize, key: &str, default: usize) -> Option<usize> {
+    use crate::sync::atomic::Ordering;
+
+    // The limit is stored off by one so that 0 can mean "not read yet" while
+    // an unlimited backtrace is still cached.
+    let limit = match cache.load(Ordering::SeqCst) {
+        0 => {
+            let limit = env::var(key)
+                .ok()
+                .and_then(|s| s.trim().parse::<usize>().ok())
+                .unwrap_or(default);
+            cache.store(limit.saturating_add(1), Ordering::SeqCst);
+            limit
+        }
+        n => n - 1,
+    };
+    if limit == 0 { None } else { Some(limit) }
+}
+
+// Returns the number of innermost frames to leave out of printed backtraces,
+// as configured through `RUST_BACKTRACE_SKIP`.
+fn frame_skip() -> usize {
+    use crate::sync::atomic::{self, Ordering};
+
+    // Stored off by one, see `env_limit`.
+    static SKIP: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+    match SKIP.load(Ordering::SeqCst) {
+        0 => {
+            let skip = env::var("RUST_BACKTRACE_SKIP")
+                .ok()
+                .and_then(|s| s.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+            SKIP.store(skip.saturating_add(1), Ordering::SeqCst);
+            skip
+        }
+        n => n - 1,
+    }
+}
+
+// Returns how long resolving the frames of a backtrace may take, as configured
+// in milliseconds through `RUST_BACKTRACE_TIMEOUT_MS`. Once it's used up, the
+// remaining frames are printed as bare addresses.
+fn resolve_budget() -> Option<Duration> {
+    use crate::sync::atomic::{self, Ordering};
+
+    // 0 means "not read yet", 1 that there is no budget and anything else is
+    // the budget offset by two.
+    static BUDGET: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+    match BUDGET.load(Ordering::SeqCst) {
+        0 => {}
+        1 => return None,
+        n => return Some(Duration::from_millis((n - 2) as u64)),
+    }
+    let millis = env::var("RUST_BACKTRACE_TIMEOUT_MS")
+        .ok()
+        .and_then(|s| s.trim().parse::<usize>().ok());
+    BUDGET.store(millis.map_or(1, |m| m.saturating_add(2)), Ordering::SeqCst);
+    millis.map(|m| Duration::from_millis(m as u64))
+}
+
+// Returns the substrings configured through `RUST_BACKTRACE_FILTER`, frames with
+// a symbol name containing any of them are left out of the backtrace. Full
+// backtraces are only filtered if `RUST_BACKTRACE_FILTER_FULL=1` as well.
+fn symbol_filters(print_fmt: PrintFmt) -> &'static [String] {
+    use crate::sync::Once;
+
+    static INIT: Once = Once::new();
+    static mut FILTERS: Vec<String> = Vec::new();
+    if print_fmt == PrintFmt::Full && !env_flag(Flag::FilterFull) {
+        return &[];
+    }
+    unsafe {
+        INIT.call_once(|| FILTERS = env_list("RUST_BACKTRACE_FILTER"));
+        &FILTERS
+    }
+}
+
+// Returns the substrings of the demangled symbol names of the entry points of
+// async executors in which `RUST_BACKTRACE_ASYNC=1` draws the line between the
+// executor and the futures it polls, see `ExecutorBoundary`. Only the paths of
+// well known runtimes are recognized by default, more can be added through
+// `RUST_BACKTRACE_EXECUTORS`, separated by commas. Nothing is returned unless
+// enabled.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn executor_patterns() -> &'static [String] {
+    use crate::sync::Once;
+
+    const DEFAULT_PATTERNS: &[&str] = &[
+        "tokio::runtime::",
+        "tokio_executor::",
+        "futures_executor::",
+        "async_std::task::",
+        "async_executor::",
+    ];
+
+    static INIT: Once = Once::new();
+    static mut PATTERNS: Vec<String> = Vec::new();
+    if !env_flag(Flag::Async) {
+        return &[];
+    }
+    unsafe {
+        INIT.call_once(|| {
+            PATTERNS = DEFAULT_PATTERNS.iter().map(|&p| String::from(p)).collect();
+            PATTERNS.extend(env_list("RUST_BACKTRACE_EXECUTORS"));
+        });
+        &PATTERNS
+    }
+}
+
+// Returns the prefixes configured through `RUST_BACKTRACE_ONLY`, usually crate
+// names. If there are any, only frames with a demangled symbol name starting
+// with one of them are printed.
+fn crate_allowlist() -> &'static [String] {
+    use crate::sync::Once;
+
+    static INIT: Once = Once::new();
+    static mut PREFIXES: Vec<String> = Vec::new();
+    unsafe {
+        INIT.call_once(|| PREFIXES = env_list("RUST_BACKTRACE_ONLY"));
+        &PREFIXES
+    }
+}
+
+// Returns the comma separated entries of the environment variable `key`, with
+// surrounding whitespace and empty entries left out.
+fn env_list(key: &str) -> Vec<String> {
+    match env::var(key) {
+        Ok(list) => list
+            .split(',')
+            .map(|entry| entry.trim())
+            .filter(|entry| !entry.is_empty())
+            .map(String::from)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Returns what to print after each frame so they can be told apart regardless
+// of how many lines they take up, as configured through `RUST_BACKTRACE_DELIM`.
+// `nul` stands for a NUL byte, anything else is printed as it is.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn frame_delimiter() -> Option<&'static str> {
+    use crate::sync::Once;
+
+    static INIT: Once = Once::new();
+    static mut DELIMITER: Option<String> = None;
+    unsafe {
+        INIT.call_once(|| {
+            DELIMITER = match env::var("RUST_BACKTRACE_DELIM") {
+                Ok(ref delimiter) if delimiter == "nul" => Some("\0".to_string()),
+                Ok(delimiter) => Some(delimiter).filter(|d| !d.is_empty()),
+                Err(_) => None,
+            };
+        });
+        DELIMITER.as_ref().map(|delimiter| &delimiter[..])
+    }
+}
+
+// Returns what to put in front of every line of text backtraces, as configured
+// through `RUST_BACKTRACE_LINE_PREFIX`, see `LinePrefix`.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn line_prefix() -> Option<&'static str> {
+    use crate::sync::Once;
+
+    static INIT: Once = Once::new();
+    static mut PREFIX: Option<String> = None;
+    unsafe {
+        INIT.call_once(|| {
+            PREFIX = env::var("RUST_BACKTRACE_LINE_PREFIX").ok().filter(|p| !p.is_empty());
+        });
+        PREFIX.as_ref().map(|prefix| &prefix[..])
+    }
+}
+
+/// Which symbol names are printed demangled, see `demangle_style`.
+#[cfg(not(feature = "backtrace-minimal"))]
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Demangle {
+    /// Every name, in whichever mangling scheme it turns out to be.
+    Auto,
+    /// Only names in the legacy mangling scheme, the others are printed as
+    /// they are in the binary.
+    OnlyLegacy,
+    /// Only names in the v0 mangling scheme, the others are printed as they
+    /// are in the binary.
+    OnlyV0,
+    /// No name, they are printed as they are in the binary.
+    None,
+}
+
+#[cfg(not(feature = "backtrace-minimal"))]
+impl Demangle {
+    /// Returns whether the mangled symbol name `raw` is printed demangled.
+    ///
+    /// The schemes are told apart by the prefixes the demangler recognizes
+    /// them by, including the ones of platforms adding an underscore. This
+    /// only picks the names handed to the demangler, which still detects the
+    /// scheme of each of them on its own.
+    fn applies_to(self, raw: &[u8]) -> bool {
+        let legacy = || ["_ZN", "ZN", "__ZN"].iter().any(|p| raw.starts_with(p.as_bytes()));
+        let v0 = || ["_R", "R", "__R"].iter().any(|p| raw.starts_with(p.as_bytes()));
+        match self {
+            Demangle::Auto => true,
+            Demangle::OnlyLegacy => legacy(),
+            Demangle::OnlyV0 => v0(),
+            Demangle::None => false,
+        }
+    }
+}
+
+// Returns which symbol names are demangled, as configured through
+// `RUST_BACKTRACE_DEMANGLE=only-v0`, `only-legacy` or `none`. The first two only
+// demangle the names of that scheme and leave the others as they are, for
+// binaries mixing them where telling the schemes apart goes wrong.
+// `RUST_BACKTRACE_MANGLED=1` is the same as `none`. Every name is demangled if
+// neither is set.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn demangle_style() -> Demangle {
+    use crate::sync::atomic::{AtomicUsize, Ordering};
+
+    static STYLE: AtomicUsize = AtomicUsize::new(0);
+    match STYLE.load(Ordering::SeqCst) {
+        0 => {}
+        1 => return Demangle::Auto,
+        2 => return Demangle::OnlyLegacy,
+        3 => return Demangle::OnlyV0,
+        _ => return Demangle::None,
+    }
+
+    let style = match env::var("RUST_BACKTRACE_DEMANGLE") {
+        Ok(ref s) if s == "only-legacy" => Demangle::OnlyLegacy,
+        Ok(ref s) if s == "only-v0" => Demangle::OnlyV0,
+        Ok(ref s) if s == "none" => Demangle::None,
+        _ if env_flag(Flag::Mangled) => Demangle::None,
+        _ => Demangle::Auto,
+    };
+    STYLE.store(
+        match style {
+            Demangle::Auto => 1,
+            Demangle::OnlyLegacy => 2,
+            Demangle::OnlyV0 => 3,
+            Demangle::None => 4,
+        },
+        Ordering::SeqCst,
+    );
+    style
+}
+
+// Returns the column locations of frames are aligned to in full backtraces, as
+// configured through `RUST_BACKTRACE_ALIGN`, or `None` if they are printed on a
+// line of their own. Locations of symbols reaching past it follow them after a
+// space.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn align_column() -> Option<usize> {
+    use crate::sync::atomic::AtomicUsize;
+
+    static ALIGN: AtomicUsize = AtomicUsize::new(0);
+    env_limit(&ALIGN, "RUST_BACKTRACE_ALIGN", 0)
+}
+
+// Returns the length symbol names are truncated to, as configured through
+// `RUST_BACKTRACE_MAX_SYMBOL_WIDTH`, or `None` if they are printed in full.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn symbol_width() -> Option<usize> {
+    use crate::sync::atomic::AtomicUsize;
+
+    static WIDTH: AtomicUsize = AtomicUsize::new(0);
+    env_limit(&WIDTH, "RUST_BACKTRACE_MAX_SYMBOL_WIDTH", 0)
+}
+
+// Returns the length above which paths are shortened, if enabled through
+// `RUST_BACKTRACE_SHORTEN_PATHS=1`. The length defaults to 80 characters and
+// can be changed through `RUST_BACKTRACE_PATH_WIDTH`.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn path_width() -> Option<usize> {
+    use crate::sync::atomic::{self, Ordering};
+
+    // 0 means "not read yet", 1 that shortening is disabled and anything else
+    // is the width offset by two.
+    static WIDTH: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+    match WIDTH.load(Ordering::SeqCst) {
+        0 => {}
+        1 => return None,
+        n => return Some(n - 2),
+    }
+    let width = if env_flag(Flag::ShortenPaths) {
+        Some(
+            env::var("RUST_BACKTRACE_PATH_WIDTH")
+                .ok()
+                .and_then(|s| s.trim().parse::<usize>().ok())
+                .unwrap_or(80),
+        )
+    } else {
+        None
+    };
+    WIDTH.store(width.map_or(1, |w| w.saturating_add(2)), Ordering::SeqCst);
+    width
+}
+
+/// The settings enabled by setting an environment variable to `1`, see
+/// `env_flag`.
+#[cfg_attr(feature = "backtrace-minimal", allow(dead_code))]
+#[derive(Copy, Clone)]
+enum Flag {
+    /// Short backtraces print the whole stack, see `is_trimmed`.
+    NoTrim,
+    /// Full backtraces are filtered as well, see `symbol_filters`.
+    FilterFull,
+    /// The frames of async executors are set apart, see `executor_patterns`.
+    Async,
+    /// Full backtraces print the instruction pointer of each frame beneath
+    /// it as `ip=0x...`.
+    Regs,
+    /// Frames are printed innermost last.
+    Reverse,
+    /// Symbol names are printed as they are in the binary, see
+    /// `demangle_style`.
+    Mangled,
+    /// Full backtraces print the line of source code of each frame.
+    Source,
+    /// Short backtraces print absolute paths as they are, without stripping
+    /// any prefixes.
+    AbsolutePaths,
+    /// Backtraces are headed by how long the process has been running.
+    Uptime,
+    /// Frames are indented by their index, so that backtraces read like a
+    /// call tree.
+    Tree,
+    /// Backtraces are delimited by lines marking their beginning and end.
+    Markers,
+    /// Short backtraces mark the frames of the user's own code.
+    UserFrames,
+    /// The notes following a backtrace are left out.
+    Quiet,
+    /// Backtraces are followed by additional notes useful when looking into
+    /// why they lack details.
+    Verbose,
+    /// Backtraces are written out and flushed line by line as they are
+    /// printed.
+    FlushEach,
+    /// `print_all_threads` interrupts the other threads to walk their stacks.
+    InterruptThreads,
+    /// Paths rewritten relative to a directory are printed with forward
+    /// slashes even where the platform uses another separator.
+    PosixPaths,
+    /// The home directory is left out of printed paths.
+    RedactHome,
+    /// Long paths are shortened, see `path_width`.
+    ShortenPaths,
+}
+
+impl Flag {
+    /// Returns the environment variable enabling the flag.
+    fn var(self) -> &'static str {
+        match self {
+            Flag::NoTrim => "RUST_BACKTRACE_NO_TRIM",
+            Flag::FilterFull => "RUST_BACKTRACE_FILTER_FULL",
+            Flag::Async => "RUST_BACKTRACE_ASYNC",
+            Flag::Regs => "RUST_BACKTRACE_REGS",
+            Flag::Reverse => "RUST_BACKTRACE_REVERSE",
+            Flag::Mangled => "RUST_BACKTRACE_MANGLED",
+            Flag::Source => "RUST_BACKTRACE_SOURCE",
+            Flag::AbsolutePaths => "RUST_BACKTRACE_ABSOLUTE_PATHS",
+            Flag::Uptime => "RUST_BACKTRACE_UPTIME",
+            Flag::Tree => "RUST_BACKTRACE_TREE",
+            Flag::Markers => "RUST_BACKTRACE_MARKERS",
+            Flag::UserFrames => "RUST_BACKTRACE_USER_FRAMES",
+            Flag::Quiet => "RUST_BACKTRACE_QUIET",
+            Flag::Verbose => "RUST_BACKTRACE_VERBOSE",
+            Flag::FlushEach => "RUST_BACKTRACE_FLUSH_EACH",
+            Flag::InterruptThreads => "RUST_BACKTRACE_INTERRUPT_THREADS",
+            Flag::PosixPaths => "RUST_BACKTRACE_POSIX_PATHS",
+            Flag::RedactHome => "RUST_BACKTRACE_REDACT_HOME",
+            Flag::ShortenPaths => "RUST_BACKTRACE_SHORTEN_PATHS",
+        }
+    }
+}
+
+// Returns whether the environment variable of `flag` is set to `1`. Each one
+// is only read once, the answers are cached as one bit per flag.
+fn env_flag(flag: Flag) -> bool {
+    use crate::sync::atomic::{AtomicUsize, Ordering};
+
+    static READ: AtomicUsize = AtomicUsize::new(0);
+    static ENABLED: AtomicUsize = AtomicUsize::new(0);
+    let bit = 1 << flag as usize;
+    if READ.load(Ordering::SeqCst) & bit != 0 {
+        return ENABLED.load(Ordering::SeqCst) & bit != 0;
+    }
+    let enabled = env::var_os(flag.var()).map_or(false, |x| &x == "1");
+    if enabled {
+        ENABLED.fetch_or(bit, Ordering::SeqCst);
+    }
+    READ.fetch_or(bit, Ordering::SeqCst);
+    enabled
+}
+
+/// The layout used for the frames of a printed backtrace.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum OutputStyle {
+    /// The human readable layout of `BacktraceFmt`.
+    Text,
+    /// A JSON array with one object per frame.
+    Json,
+    /// A single line with the names of the frames, outermost first.
+    Compact,
+    /// The instruction pointers of the frames, one per line, without
+    /// resolving them.
+    Addrs,
+}
+
+// Returns the layout requested through `RUST_BACKTRACE_FORMAT`, or through
+// `RUST_BACKTRACE=compact` and `RUST_BACKTRACE=addrs`, defaulting to the human
+// readable one for unset or unrecognized values.
+pub fn output_style() -> OutputStyle {
+    use crate::sync::atomic::{self, Ordering};
+
+    static STYLE: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+    match STYLE.load(Ordering::SeqCst) {
+        0 => {}
+        1 => return OutputStyle::Text,
+        2 => return OutputStyle::Json,
+        3 => return OutputStyle::Compact,
+        _ => return OutputStyle::Addrs,
+    }
+
+    let style = match env::var_os("RUST_BACKTRACE_FORMAT") {
+        Some(ref x) if x == "json" => OutputStyle::Json,
+        _ if compact_enabled() => OutputStyle::Compact,
+        _ if addrs_enabled() => OutputStyle::Addrs,
+        _ => OutputStyle::Text,
+    };
+    STYLE.store(
+        match style {
+            OutputStyle::Text => 1,
+            OutputStyle::Json => 2,
+            OutputStyle::Compact => 3,
+            OutputStyle::Addrs => 4,
+        },
+        Ordering::SeqCst,
+    );
+    style
+}
+
+// Returns whether a backtrace printed to a writer should be colored, as
+// configured through `RUST_BACKTRACE_COLOR`. Unset or unrecognized values mean
+// `auto`, which colors the output only if it goes to a terminal.
+//
+// The conventions shared with other tools take precedence: a non-empty
+// `CLICOLOR_FORCE` always enables colors, and otherwise setting `NO_COLOR`
+// disables them.
+fn color_enabled(is_terminal: bool) -> bool {
+    use crate::sync::atomic::{self, Ordering};
+
+    static COLOR: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+    let choice = match COLOR.load(Ordering::SeqCst) {
+        0 => {
+            let force = env::var_os("CLICOLOR_FORCE").map_or(false, |x| !x.is_empty());
+            let choice = if force {
+                2
+            } else if env::var_os("NO_COLOR").is_some() {
+                1
+            } else {
+                match env::var_os("RUST_BACKTRACE_COLOR") {
+                    Some(ref x) if x == "never" => 1,
+                    Some(ref x) if x == "always" => 2,
+                    _ => 3,
+                }
+            };
+            COLOR.store(choice, Ordering::SeqCst);
+            choice
+        }
+        n => n,
+    };
+    match choice {
+        1 => false,
+        2 => true,
+        _ => is_terminal,
+    }
+}
+
+// For now logging is turned off by default, and this function checks to see
+// whether the magical environment variable is present to see if it's turned on.
+pub fn log_enabled() -> Option<PrintFmt> {
+    match backtrace_env() {
+        1 => None,
+        2 | 5 | 6 => Some(PrintFmt::Short),
+        // `RUST_BACKTRACE=symbols` prints full backtraces, since short ones
+        // leave out the offsets.
+        _ => Some(PrintFmt::Full),
+    }
+}
+
+// Returns whether backtraces are printed on a single line, as configured
+// through `RUST_BACKTRACE=compact`. They are trimmed like short backtraces.
+fn compact_enabled() -> bool {
+    !cfg!(target_os = "fuchsia") && backtrace_env() == 5
+}
+
+// Returns whether backtraces are printed as bare instruction pointers, as
+// configured through `RUST_BACKTRACE=addrs`. They are trimmed like short
+// backtraces.
+fn addrs_enabled() -> bool {
+    !cfg!(target_os = "fuchsia") && backtrace_env() == 6
+}
+
+// Returns whether frames are printed as their symbols and offsets only,
+// without any paths, as configured through `RUST_BACKTRACE=symbols`. Such
+// backtraces are full ones otherwise.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn symbols_enabled() -> bool {
+    !cfg!(target_os = "fuchsia") && backtrace_env() == 7
+}
+
+// Returns whether full backtraces leave out the frames of the standard
+// library, as configured through `RUST_BACKTRACE=full-no-std`.
+fn std_frames_hidden() -> bool {
+    !cfg!(target_os = "fuchsia") && backtrace_env() == 4
+}
+
+// Returns the setting of `RUST_BACKTRACE`, which is 1 if backtraces are
+// disabled, 2 for short ones, 3 for full ones, 4 for full ones without the
+// frames of the standard library, 5 for compact ones, 6 for bare addresses and
+// 7 for symbols without paths.
+//
+// Setting environment variables for Fuchsia components isn't a standard or
+// easily supported workflow, so there full backtraces are printed unless
+// `RUST_BACKTRACE` is set.
+fn backtrace_env() -> isize {
+    use crate::sync::atomic::{self, Ordering};
+
+    static ENABLED: atomic::AtomicIsize = atomic::AtomicIsize::new(0);
+    match ENABLED.load(Ordering::SeqCst) {
+        0 => {}
+        n => return n,
+    }
+
+    let val = match env::var_os(BACKTRACE_VAR) {
+        None if cfg!(target_os = "fuchsia") => 3,
+        None => 1,
+        Some(ref x) if x.is_empty() || x == "0" => 1,
+        Some(ref x) if x == "1" || x == "short" => 2,
+        Some(ref x) if x == "full" => 3,
+        Some(ref x) if x == "full-no-std" => 4,
+        Some(ref x) if x == "compact" => 5,
+        Some(ref x) if x == "addrs" => 6,
+        Some(ref x) if x == "symbols" => 7,
+        Some(x) => {
+            // Unknown values keep enabling short backtraces for compatibility,
+            // but typos shouldn't go unnoticed. The answer is cached below, so
+            // this is only printed once.
+            if let Some(mut out) = crate::sys::stdio::panic_output() {
+                let _ = writeln!(
+                    out,
+                    "warning: unrecognized value {:?} for {}, expected `0`, \
+                     `1`, `short`, `full`, `full-no-std`, `compact`, `addrs` or `symbols`; \
+                     printing a short backtrace",
+                    x,
+                    BACKTRACE_VAR
+                );
+            }
+            2
+        }
+    };
+    ENABLED.store(val, Ordering::SeqCst);
+    val
+}
+
+/// Prints the filename of the backtrace frame.
+///
+/// In short mode absolute paths are shortened if they start with one of
+/// `prefixes` or with `cwd`, trying them in that order. Prefixes in cargo's
+/// home directory are replaced with `<registry>`, any other one with `.`.
+/// Before that, code generated by build scripts has the output directory it
+/// was generated in replaced with `<out>`, see `out_dir_path`. Neither is done
+/// if `RUST_BACKTRACE_ABSOLUTE_PATHS=1`.
+///
+/// In either mode, paths which weren't shortened like that have the home
+/// directory replaced with `~` if enabled through `RUST_BACKTRACE_REDACT_HOME=1`,
+/// to keep user names out of crash reports.
+///
+/// Before any of that, paths are rewritten as mapped in the file named by
+/// `RUST_BACKTRACE_REMAP`, see `path_remaps`.
+///
+/// See also `output`.
+#[cfg(not(feature = "backtrace-minimal"))]
+pub fn output_filename(
+    fmt: &mut fmt::Formatter<'_>,
+    bows: BytesOrWideString<'_>,
+    print_fmt: PrintFmt,
+    cwd: Option<&PathBuf>,
+    prefixes: &[PathBuf],
+) -> fmt::Result {
+    let file = remap_path(bows_to_path(bows), path_remaps());
+    // Long paths on Windows may come with the verbatim prefix, which would
+    // keep them from matching any prefix and isn't worth printing either.
+    let file = match strip_verbatim(&file) {
+        Some(clean) => Cow::Owned(clean),
+        None => file,
+    };
+    let clean_cwd = cwd.and_then(|cwd| strip_verbatim(cwd));
+    let cwd = clean_cwd.as_ref().or(cwd).map(|cwd| &**cwd);
+    let mut relative = None;
+    let rewrite =
+        print_fmt == PrintFmt::Short && file.is_absolute() && !env_flag(Flag::AbsolutePaths);
+    if let Some(stripped) = out_dir_path(&file).filter(|_| rewrite) {
+        relative = stripped.to_str().map(|s| ("<out>", s));
+    }
+    if rewrite && relative.is_none() {
+        for prefix in prefixes.iter().map(|prefix| &**prefix).chain(cwd) {
+            if let Ok(stripped) = file.strip_prefix(prefix) {
+                if let Some(s) = stripped.to_str() {
+                    let base = if Some(prefix) != cwd && is_cargo_home(prefix) {
+                        "<registry>"
+                    } else {
+                        "."
+                    };
+                    relative = Some((base, s));
+                    break;
+                }
+            }
+        }
+    }
+    let home = if relative.is_none() && env_flag(Flag::RedactHome) {
+        crate::sys::os::home_dir().filter(|home| home.is_absolute())
+    } else {
+        None
+    };
+    if let Some(stripped) = home.as_ref().and_then(|home| file.strip_prefix(home).ok()) {
+        relative = stripped.to_str().map(|s| ("~", s));
+    }
+
+    // Rewritten paths may use forward slashes regardless of the platform, see
+    // `Flag::PosixPaths`.
+    let sep = if env_flag(Flag::PosixPaths) { '/' } else { path::MAIN_SEPARATOR };
+    let relative = relative.map(|(base, s)| {
+        let s = if sep == path::MAIN_SEPARATOR {
+            Cow::Borrowed(s)
+        } else {
+            Cow::Owned(s.replace(path::MAIN_SEPARATOR, "/"))
+        };
+        (base, s)
+    });
+
+    if let Some(width) = path_width() {
+        let path = match relative {
+            Some((base, s)) => format!("{}{}{}", base, sep, s),
+            None => file.display().to_string(),
+        };
+        return fmt.write_str(&shorten_path(&path, width));
+    }
+    match relative {
+        Some((base, s)) => write!(fmt, "{}{}{}", base, sep, s),
+        None => fmt::Display::fmt(&file.display(), fmt),
+    }
+}
+
+/// Returns whether `file` is a source file of the user's own code, rather than
+/// one of a dependency or the standard library.
+///
+/// The file is compared against `cwd` the same way `output_filename` strips it
+/// off: after remapping it and leaving out the verbatim prefix on Windows.
+/// Files generated into the `OUT_DIR` of a build script aren't the user's own.
+/// Cargo passes relative paths to the compiler for the packages of the
+/// workspace it is building, so those always count. Only the paths are
+/// compared, the file system isn't looked at.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn is_user_file(file: &Path, cwd: Option<&PathBuf>) -> bool {
+    let file = remap_path(Cow::Borrowed(file), path_remaps());
+    let file = match strip_verbatim(&file) {
+        Some(clean) => Cow::Owned(clean),
+        None => file,
+    };
+    if out_dir_path(&file).is_some() {
+        return false;
+    }
+    if file.is_relative() {
+        return true;
+    }
+    let clean_cwd = cwd.and_then(|cwd| strip_verbatim(cwd));
+    match clean_cwd.as_ref().or(cwd) {
+        Some(cwd) => file.starts_with(cwd),
+        None => false,
+    }
+}
+
+// Shortens `path` to its first and last components if it's longer than
+// `width` characters, like `/home/.../lib.rs`. Forward slashes separate
+// components on every platform, as rewritten paths may use them.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn shorten_path(path: &str, width: usize) -> Cow<'_, str> {
+    if path.chars().count() <= width {
+        return Cow::Borrowed(path);
+    }
+    let sep = |c: char| c == path::MAIN_SEPARATOR || c == '/';
+    // Leading `/`, `.` and `<registry>` don't say much on their own, so the
+    // first component kept is the one following them.
+    let mut head = 0;
+    for part in path.split(sep) {
+        head += part.len() + 1;
+        if !part.is_empty() && part != "." && part != "<registry>" {
+            break;
+        }
+    }
+    let tail = match path.rfind(sep) {
+        // Only shorten if there's more in between than the ellipsis itself.
+        Some(tail) if tail > head + 3 => tail,
+        _ => return Cow::Borrowed(path),
+    };
+    Cow::Owned(format!("{}...{}", &path[..head], &path[tail..]))
+}
+
+// Truncates `name` to `width` characters if it's longer, ending it with `…`.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn truncate_symbol(name: &str, width: usize) -> Cow<'_, str> {
+    match name.char_indices().nth(width) {
+        Some(_) => {
+            let end = name.char_indices().nth(width - 1).map_or(0, |(i, _)| i);
+            Cow::Owned(format!("{}…", &name[..end]))
+        }
+        None => Cow::Borrowed(name),
+    }
+}
+
+/// Converts a filename reported by `backtrace` to a path, as `output_filename`
+/// prints it.
+///
+/// Byte strings are taken as paths of the platform, without interpretation on
+/// Unix and as UTF-8 elsewhere, and wide strings as UTF-16 paths on Windows.
+pub fn bows_to_path(bows: BytesOrWideString<'_>) -> Cow<'_, Path> {
+    match bows {
+        #[cfg(unix)]
+        BytesOrWideString::Bytes(bytes) => {
+            use crate::os::unix::prelude::*;
+            Path::new(crate::ffi::OsStr::from_bytes(bytes)).into()
+        }
+        // Only Unix paths are arbitrary bytes, elsewhere they are expected to
+        // be UTF-8. Invalid sequences are replaced rather than dropping the
+        // whole path, so the rest of it can still be told apart.
+        #[cfg(not(unix))]
+        BytesOrWideString::Bytes(bytes) => match String::from_utf8_lossy(bytes) {
+            Cow::Borrowed(s) => Path::new(s).into(),
+            Cow::Owned(s) => Cow::Owned(s.into()),
+        },
+        #[cfg(windows)]
+        BytesOrWideString::Wide(wide) => {
+            use crate::os::windows::prelude::*;
+            Cow::Owned(crate::ffi::OsString::from_wide(wide).into())
+        }
+        #[cfg(not(windows))]
+        BytesOrWideString::Wide(_wide) => {
+            Path::new("<unknown>").into()
+        }
+    }
+}
+
+// Returns `path` without the `\\?\` prefix of verbatim paths on Windows, which
+// are needed for paths longer than `MAX_PATH`, or `None` if it has no such
+// prefix or can't do without it.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn strip_verbatim(path: &Path) -> Option<PathBuf> {
+    use crate::path::{Component, Prefix};
+
+    let mut components = path.components();
+    let mut clean = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::VerbatimDisk(disk) => PathBuf::from(format!("{}:", disk as char)),
+            Prefix::VerbatimUNC(server, share) => {
+                let mut unc = crate::ffi::OsString::from(r"\\");
+                unc.push(server);
+                unc.push(r"\");
+                unc.push(share);
+                PathBuf::from(unc)
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+    clean.extend(components);
+    Some(clean)
+}
+
+// Returns the part of `path` inside of the `OUT_DIR` of a build script, which
+// cargo puts in `target/<profile>/build/<package>/out`. This is looked for in
+// the path of every printed frame, so it walks the components without
+// allocating.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn out_dir_path(path: &Path) -> Option<&Path> {
+    let mut components = path.components();
+    loop {
+        let mut rest = components.clone();
+        if rest.next()?.as_os_str() == "build"
+            && rest.next().is_some()
+            && rest.next().map_or(false, |c| c.as_os_str() == "out")
+        {
+            let rest = rest.as_path();
+            return if rest.as_os_str().is_empty() { None } else { Some(rest) };
+        }
+        components.next();
+    }
+}
+
+// Returns whether `path` is, or is inside of, cargo's home directory where the
+// sources of registry dependencies are unpacked.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn is_cargo_home(path: &Path) -> bool {
+    path.components().any(|c| c.as_os_str() == ".cargo") || path.ends_with("registry/src")
+}
+
+/// Returns the path prefixes to rewrite in printed paths, read once from the
+/// file named by `RUST_BACKTRACE_REMAP`.
+///
+/// Each line of the file maps a prefix to the one to print instead, like
+/// `from=to`, as with rustc's `--remap-path-prefix` but applied when printing,
+/// to make sense of paths from builds which were remapped. Nothing is
+/// rewritten if the file can't be read.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn path_remaps() -> &'static [(PathBuf, PathBuf)] {
+    use crate::sync::Once;
+
+    static INIT: Once = Once::new();
+    static mut REMAPS: Vec<(PathBuf, PathBuf)> = Vec::new();
+    unsafe {
+        INIT.call_once(|| {
+            let map = env::var_os("RUST_BACKTRACE_REMAP")
+                .and_then(|path| crate::fs::read_to_string(path).ok());
+            if let Some(map) = map {
+                REMAPS = parse_remaps(&map);
+            }
+        });
+        &REMAPS
+    }
+}
+
+/// Parses the lines of a `RUST_BACKTRACE_REMAP` file, skipping the ones which
+/// don't map anything. Like rustc, lines are split at the last `=`.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn parse_remaps(map: &str) -> Vec<(PathBuf, PathBuf)> {
+    map.lines()
+        .filter_map(|line| {
+            let mut parts = line.trim().rsplitn(2, '=');
+            let to = parts.next()?;
+            let from = parts.next().filter(|from| !from.is_empty())?;
+            Some((PathBuf::from(from), PathBuf::from(to)))
+        })
+        .collect()
+}
+
+/// Rewrites the first of the `remaps` whose prefix `file` starts with.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn remap_path<'a>(file: Cow<'a, Path>, remaps: &[(PathBuf, PathBuf)]) -> Cow<'a, Path> {
+    for (from, to) in remaps {
+        if let Ok(rest) = file.strip_prefix(from) {
+            if rest.as_os_str().is_empty() {
+                return Cow::Owned(to.clone());
+            }
+            return Cow::Owned(to.join(rest));
+        }
+    }
+    file
+}
+
+// Returns the additional prefixes configured through `RUST_BACKTRACE_STRIP_PREFIX`
+// which `output_filename` strips from paths, separated like `PATH` entries.
+#[cfg(not(feature = "backtrace-minimal"))]
+fn strip_prefixes() -> &'static [PathBuf] {
+    use crate::sync::Once;
+
+    static INIT: Once = Once::new();
+    static mut PREFIXES: Vec<PathBuf> = Vec::new();
+    unsafe {
+        INIT.call_once(|| {
+            if let Some(prefixes) = env::var_os("RUST_BACKTRACE_STRIP_PREFIX") {
+                PREFIXES = env::split_paths(&prefixes).filter(|p| p.is_absolute()).collect();
+            }
+        });
+        &PREFIXES
+    }
+}
+
+#[cfg(all(test, not(target_os = "emscripten"), not(feature = "backtrace-minimal")))]
+mod tests {
+    use super::{enter_print, format_synthetic, out_dir_path, parse_remaps, remap_path};
+    use super::shorten_path;
+    use super::{signal_name, truncate_symbol, wait_turn};
+    use super::{_print, FrameSource, OutputStyle, SyntheticFrame};
+    use crate::backtrace_rs::PrintFmt;
+    use crate::env;
+    use crate::thread;
+
+    fn frame(ip: usize, name: &str, filename: Option<&str>, lineno: u32) -> SyntheticFrame {
+        SyntheticFrame {
+            ip,
+            addr: None,
+            name: Some(name.to_string()),
+            filename: filename.map(|f| f.to_string()),
+            lineno: Some(lineno),
+            inlined: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn print_turns() {
+        use crate::time::Instant;
+
+        // Threads giving up at once still take their turn, but only get it
+        // once the ones in line before them are done.
+        let first = wait_turn(Instant::now());
+        assert!(first.is_current());
+        let second = wait_turn(Instant::now());
+        let third = wait_turn(Instant::now());
+        assert!(!second.is_current() && !third.is_current());
+        drop(first);
+        assert!(second.is_current() && !third.is_current());
+
+        // Turns done out of order, after giving up waiting, never move the
+        // current one back.
+        drop(third);
+        assert!(second.is_current());
+        drop(second);
+        assert!(wait_turn(Instant::now()).is_current());
+    }
+
+    #[test]
+    fn lock_timeout() {
+        use super::{lock, lock_until};
+        use crate::time::Instant;
+
+        let held = lock();
+        assert!(lock_until(Instant::now()).is_none());
+        drop(held);
+        assert!(lock_until(Instant::now()).is_some());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn signal_names() {
+        assert_eq!(signal_name(libc::SIGSEGV), Some("SIGSEGV"));
+        assert_eq!(signal_name(libc::SIGABRT), Some("SIGABRT"));
+        assert_eq!(signal_name(-1), None);
+    }
+
+    #[test]
+    fn print_errors() {
+        use super::PrintError;
+        use crate::error::Error;
+        use crate::io;
+
+        let err = io::Error::from(PrintError::Write(io::Error::from(io::ErrorKind::WriteZero)));
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+        assert!(err.get_ref().is_none());
+
+        let err = io::Error::from(PrintError::Symbolize);
+        assert!(match err.get_ref().and_then(|err| err.downcast_ref::<PrintError>()) {
+            Some(PrintError::Symbolize) => true,
+            _ => false,
+        });
+        let err = PrintError::File(io::Error::from(io::ErrorKind::NotFound));
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn demangle_styles() {
+        use super::Demangle;
+
+        let legacy = b"_ZN3foo3bar17h0123456789abcdefE";
+        let v0 = b"_RNvCs1234_3foo3bar";
+        assert!(Demangle::Auto.applies_to(legacy) && Demangle::Auto.applies_to(v0));
+        assert!(Demangle::OnlyLegacy.applies_to(legacy) && !Demangle::OnlyLegacy.applies_to(v0));
+        assert!(Demangle::OnlyV0.applies_to(v0) && !Demangle::OnlyV0.applies_to(legacy));
+        assert!(Demangle::OnlyV0.applies_to(b"__RNvCs1234_3foo3bar"));
+        assert!(!Demangle::None.applies_to(legacy) && !Demangle::None.applies_to(v0));
+    }
+
+    #[test]
+    fn reentrant_print() {
+        let outer = enter_print();
+        assert!(outer.is_some());
+        assert!(enter_print().is_none());
+
+        // Other threads print independently.
+        thread::spawn(|| assert!(enter_print().is_some())).join().unwrap();
+
+        drop(outer);
+        assert!(enter_print().is_some());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn shorten_paths() {
+        let path = "/home/user/.cargo/registry/src/github.com-1ecc6299db9ec823/tokio/src/lib.rs";
+        assert_eq!(shorten_path(path, 100), path);
+        assert_eq!(shorten_path(path, 20), "/home/.../lib.rs");
+        assert_eq!(shorten_path("./src/foo/bar/baz.rs", 10), "./src/.../baz.rs");
+        assert_eq!(
+            shorten_path("<registry>/tokio/src/runtime/mod.rs", 10),
+            "<registry>/tokio/.../mod.rs"
+        );
+        assert_eq!(shorten_path("/home/lib.rs", 5), "/home/lib.rs");
+        assert_eq!(shorten_path("/home/src/lib.rs", 5), "/home/src/lib.rs");
+        assert_eq!(shorten_path("lib.rs", 5), "lib.rs");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn remap_paths() {
+        use crate::borrow::Cow;
+        use crate::path::{Path, PathBuf};
+
+        let remaps = parse_remaps("/x/src=/home/me/src\n\n  /y=z=/opt  \nno mapping\n=/a\n");
+        assert_eq!(
+            remaps,
+            [
+                (PathBuf::from("/x/src"), PathBuf::from("/home/me/src")),
+                (PathBuf::from("/y=z"), PathBuf::from("/opt")),
+            ]
+        );
+        let remap = |path| remap_path(Cow::Borrowed(Path::new(path)), &remaps);
+        assert_eq!(remap("/x/src/lib.rs"), Path::new("/home/me/src/lib.rs"));
+        assert_eq!(remap("/x/src"), Path::new("/home/me/src"));
+        assert_eq!(remap("/x/srcs/lib.rs"), Path::new("/x/srcs/lib.rs"));
+        assert_eq!(remap("/y=z/a.rs"), Path::new("/opt/a.rs"));
+    }
+
+    #[test]
+    #[cfg(windows)]
+    fn verbatim_paths() {
+        use super::{output_filename, strip_verbatim};
+        use crate::backtrace_rs::BytesOrWideString;
+        use crate::fmt;
+        use crate::path::{Path, PathBuf};
+
+        struct Filename<'a>(&'a str, &'a PathBuf);
+
+        impl fmt::Display for Filename<'_> {
+            fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let bows = BytesOrWideString::Bytes(self.0.as_bytes());
+                output_filename(fmt, bows, PrintFmt::Short, Some(self.1), &[])
+            }
+        }
+
+        let dir = format!(r"C:\{}", ["very long directory name"; 12].join(r"\"));
+        let file = format!(r"\\?\{}\src\lib.rs", dir);
+        assert!(file.len() > 260);
+        let clean = strip_verbatim(Path::new(&file)).unwrap();
+        assert_eq!(clean, Path::new(&format!(r"{}\src\lib.rs", dir)));
+        assert_eq!(clean.strip_prefix(&dir).unwrap(), Path::new(r"src\lib.rs"));
+
+        let unc = strip_verbatim(Path::new(r"\\?\UNC\server\share\lib.rs")).unwrap();
+        assert_eq!(unc, Path::new(r"\\server\share\lib.rs"));
+        assert_eq!(strip_verbatim(Path::new(r"C:\src\lib.rs")), None);
+
+        // Frames in the current directory are printed relative to it, whether
+        // either path is verbatim or not.
+        let verbatim_dir = PathBuf::from(format!(r"\\?\{}", dir));
+        let plain_file = format!(r"{}\src\lib.rs", dir);
+        for &(file, cwd) in &[(&file, &PathBuf::from(&dir)), (&plain_file, &verbatim_dir)] {
+            assert_eq!(Filename(file, cwd).to_string(), r".\src\lib.rs");
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn out_dir_paths() {
+        use crate::path::Path;
+
+        let path = "/src/foo/target/debug/build/foo-1234abcd/out/bindings.rs";
+        assert_eq!(out_dir_path(Path::new(path)), Some(Path::new("bindings.rs")));
+        let path = "/target/release/build/foo-1234abcd/out/gen/mod.rs";
+        assert_eq!(out_dir_path(Path::new(path)), Some(Path::new("gen/mod.rs")));
+        assert_eq!(out_dir_path(Path::new("/target/debug/build/foo-1234abcd/out")), None);
+        assert_eq!(out_dir_path(Path::new("/src/foo/build/lib.rs")), None);
+        assert_eq!(out_dir_path(Path::new("build/foo/out/lib.rs")), Some(Path::new("lib.rs")));
+    }
+
+    // Frames in the current directory are printed without allocating on Unix,
+    // as backtraces may well be captured for every error of a busy server.
+    #[bench]
+    #[cfg(unix)]
+    fn bench_output_filename_relative(b: &mut test::Bencher) {
+        use super::output_filename;
+        use crate::backtrace_rs::BytesOrWideString;
+        use crate::fmt::{self, Write};
+        use crate::path::PathBuf;
+
+        struct Filename<'a>(&'a str, &'a PathBuf);
+
+        impl fmt::Display for Filename<'_> {
+            fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let bows = BytesOrWideString::Bytes(self.0.as_bytes());
+                output_filename(fmt, bows, PrintFmt::Short, Some(self.1), &[])
+            }
+        }
+
+        let cwd = PathBuf::from("/home/user/src/server");
+        let file = "/home/user/src/server/src/handlers/request.rs";
+        let mut s = String::with_capacity(64);
+        b.iter(|| {
+            s.clear();
+            write!(s, "{}", Filename(file, &cwd)).unwrap();
+            test::black_box(&s);
+        });
+        assert_eq!(s, "./src/handlers/request.rs");
+    }
+
+    #[test]
+    fn synthetic_frames() {
+        let frames = vec![
+            frame(0x10, "foo::inner", Some("/src/foo.rs"), 3),
+            frame(0x20, "foo::outer", Some("/src/foo.rs"), 7),
+        ];
+        let s = format_synthetic(frames, PrintFmt::Full, OutputStyle::Text);
+        let lines: Vec<&str> = s.lines().filter(|l| l.contains(" - ")).collect();
+        assert_eq!(lines.len(), 2, "{}", s);
+        assert!(lines[0].starts_with("   0: ") && lines[0].ends_with("0x10 - foo::inner"), "{}", s);
+        assert!(lines[1].starts_with("   1: ") && lines[1].ends_with("0x20 - foo::outer"), "{}", s);
+        assert!(s.contains("at /src/foo.rs:7"), "{}", s);
+        assert!(!s.contains("note: backtrace contained"), "{}", s);
+    }
+
+    #[test]
+    fn user_frames() {
+        use super::{is_user_file, FrameFmt};
+        use crate::backtrace_rs::{BytesOrWideString, SymbolName};
+        use crate::ffi::c_void;
+        use crate::fmt;
+        use crate::path::{Path, PathBuf};
+
+        struct Classified<'a>(&'a [SyntheticFrame], &'a PathBuf);
+        impl fmt::Display for Classified<'_> {
+            fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let mut f = FrameFmt::new(fmt, PrintFmt::Short, false, Some(self.1), &[]);
+                f.classify = true;
+                for frame in self.0 {
+                    f.print_raw(
+                        frame.ip as *mut c_void,
+                        false,
+                        frame.name.as_ref().map(|n| SymbolName::new(n.as_bytes())),
+                        None,
+                        frame.filename.as_ref().map(|f| BytesOrWideString::Bytes(f.as_bytes())),
+                        frame.lineno,
+                    )?;
+                    f.frame_index += 1;
+                }
+                Ok(())
+            }
+        }
+
+        let cwd = env::current_dir().unwrap();
+        let own = cwd.join("src").join("main.rs");
+        let out = cwd.join("target").join("debug").join("build").join("foo-1234").join("out");
+        let out = out.join("bindings.rs");
+        let frames = vec![
+            frame(0x10, "foo::inner", own.to_str(), 3),
+            frame(0x20, "foo::relative", Some("src/missing.rs"), 4),
+            frame(0x28, "std::panicking::begin_panic", own.to_str(), 5),
+            frame(0x30, "bar::dependency", Some("/registry/bar/src/lib.rs"), 5),
+            frame(0x40, "foo::generated", out.to_str(), 6),
+            frame(0x50, "foo::unknown", None, 7),
+        ];
+        let s = Classified(&frames, &cwd).to_string();
+        let lines: Vec<&str> = s.lines().filter(|l| l.contains(": ")).collect();
+        assert!(lines[0].starts_with(">  0: foo::inner"), "{}", s);
+        assert!(lines[1].starts_with(">  1: foo::relative"), "{}", s);
+        assert!(lines[2].starts_with("   2: std::panicking::begin_panic"), "{}", s);
+        assert!(lines[3].starts_with("   3: bar::dependency"), "{}", s);
+        assert!(lines[4].starts_with("   4: foo::generated"), "{}", s);
+        assert!(lines[5].starts_with("   5: foo::unknown"), "{}", s);
+
+        // Frames aren't marked unless asked to.
+        let s = format_synthetic(frames, PrintFmt::Short, OutputStyle::Text);
+        assert!(!s.lines().any(|l| l.starts_with('>')), "{}", s);
+
+        // Relative paths always count, absolute ones only under `cwd`.
+        assert!(is_user_file(Path::new("src/missing.rs"), None));
+        assert!(is_user_file(&own, Some(&cwd)));
+        assert!(!is_user_file(&own, None));
+    }
+
+    #[test]
+    fn timestamps() {
+        use super::Timestamp;
+
+        assert_eq!(Timestamp(0).to_string(), "1970-01-01T00:00:00Z");
+        assert_eq!(Timestamp(951_782_400).to_string(), "2000-02-29T00:00:00Z");
+        assert_eq!(Timestamp(1_571_056_496).to_string(), "2019-10-14T12:34:56Z");
+        assert_eq!(Timestamp(4_107_628_799).to_string(), "2100-03-01T23:59:59Z");
+    }
+
+    #[test]
+    fn append_to_file() {
+        use crate::fs;
+        use crate::sys_common::io::test::tmpdir;
+
+        let dir = tmpdir();
+        let path = dir.join("backtraces.txt");
+        super::append_to_file(&path, b"   0: \x1b[1mfoo::bar\x1b[0m\n").unwrap();
+        super::append_to_file(&path, b"   0: baz\n").unwrap();
+        let s = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = s.lines().collect();
+        assert_eq!(lines.len(), 4, "{}", s);
+        assert!(lines[0].starts_with("backtrace of process "), "{}", s);
+        assert_eq!(lines[1], "   0: foo::bar");
+        assert_eq!(lines[3], "   0: baz");
+    }
+
+    #[test]
+    #[cfg(any(unix, windows))]
+    fn print_from_context_unsupported() {
+        use crate::io;
+
+        let mut buf = Vec::new();
+        let err = unsafe { super::print_from_context(&mut buf, crate::ptr::null_mut()) };
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::Other);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn corrupt_frames() {
+        let unresolved = |ip| SyntheticFrame {
+            ip,
+            addr: None,
+            name: None,
+            filename: None,
+            lineno: None,
+            inlined: Vec::new(),
+        };
+        let frames = vec![
+            frame(0x10, "foo::inner", None, 3),
+            unresolved(0x7),
+            unresolved(0x10_0000),
+            unresolved(0),
+        ];
+        let s = format_synthetic(frames.clone(), PrintFmt::Full, OutputStyle::Text);
+        let lines: Vec<&str> = s.lines().filter(|l| l.contains(" - ")).collect();
+        assert!(lines[0].ends_with(" - foo::inner"), "{}", s);
+        assert!(lines[1].ends_with(" - <corrupt frame>"), "{}", s);
+        assert!(lines[2].ends_with(" - <unknown>"), "{}", s);
+        assert!(lines[3].ends_with(" - <corrupt frame>"), "{}", s);
+
+        // Null frames are still left out of short backtraces.
+        let s = format_synthetic(frames, PrintFmt::Short, OutputStyle::Text);
+        assert_eq!(s.matches("<corrupt frame>").count(), 1, "{}", s);
+    }
+
+    #[test]
+    fn tree_indent() {
+        use super::{FrameFmt, MAX_TREE_DEPTH};
+        use crate::backtrace_rs::{BytesOrWideString, SymbolName};
+        use crate::ffi::c_void;
+        use crate::fmt;
+
+        struct Tree(usize);
+        impl fmt::Display for Tree {
+            fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let mut f = FrameFmt::new(fmt, PrintFmt::Short, false, None, &[]);
+                f.tree = true;
+                for _ in 0..self.0 {
+                    f.print_raw(
+                        0x10 as *mut c_void,
+                        false,
+                        Some(SymbolName::new(b"foo::bar")),
+                        None,
+                        Some(BytesOrWideString::Bytes(b"/src/foo.rs")),
+                        Some(3),
+                    )?;
+                    f.frame_index += 1;
+                }
+                Ok(())
+            }
+        }
+        let s = Tree(2).to_string();
+        let lines: Vec<&str> = s.lines().collect();
+        assert_eq!(lines[0], "   0: foo::bar");
+        assert_eq!(lines[1], "             at /src/foo.rs:3");
+        assert_eq!(lines[2], "     1: foo::bar");
+        assert_eq!(lines[3], "               at /src/foo.rs:3");
+
+        // The indentation stops growing at some point.
+        let s = Tree(MAX_TREE_DEPTH + 2).to_string();
+        let last = s.lines().rev().nth(1).unwrap();
+        let indent = format!("{:1$}", "", 2 * MAX_TREE_DEPTH);
+        assert!(last.starts_with(&format!("{}  {}:", indent, MAX_TREE_DEPTH + 1)), "{}", s);
+    }
+
+    #[test]
+    fn symbols_only() {
+        use super::FrameFmt;
+        use crate::backtrace_rs::{BytesOrWideString, SymbolName};
+        use crate::ffi::c_void;
+        use crate::fmt;
+
+        // Prints `foo::bar`, and if `inlined` the frame it was inlined into.
+        struct Symbols(bool);
+        impl fmt::Display for Symbols {
+            fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let mut f = FrameFmt::new(fmt, PrintFmt::Full, false, None, &[]);
+                f.symbols_only = true;
+                let names: &[&[u8]] =
+                    if self.0 { &[b"foo::bar", b"foo::outer"] } else { &[b"foo::bar"] };
+                for (i, name) in names.iter().enumerate() {
+                    f.print_raw(
+                        0x1010 as *mut c_void,
+                        i + 1 < names.len(),
+                        Some(SymbolName::new(name)),
+                        Some(0x1000 as *mut c_void),
+                        Some(BytesOrWideString::Bytes(b"/src/foo.rs")),
+                        Some(3),
+                    )?;
+                }
+                Ok(())
+            }
+        }
+        assert_eq!(Symbols(false).to_string(), "   0: foo::bar+0x10\n");
+        let inlined = "   0: foo::bar+0x10 (inlined)\n        foo::outer+0x10\n";
+        assert_eq!(Symbols(true).to_string(), inlined);
+    }
+
+    #[test]
+    fn synthetic_inlined() {
+        let frames = vec![
+            SyntheticFrame {
+                inlined: vec!["foo::inner".to_string()],
+                ..frame(0x10, "foo::outer", Some("/src/foo.rs"), 3)
+            },
+            frame(0x20, "foo::main", Some("/src/foo.rs"), 7),
+        ];
+        let s = format_synthetic(frames, PrintFmt::Full, OutputStyle::Text);
+        let lines: Vec<&str> = s.lines().collect();
+        // Only the first symbol of the frame carries its index, the function
+        // it was inlined into is indented below it.
+        let inner = lines.iter().position(|l| l.contains("foo::inner")).expect(&s);
+        assert!(lines[inner].starts_with("   0: "), "{}", s);
+        assert!(lines[inner].ends_with(" (inlined)"), "{}", s);
+        let outer = lines.iter().position(|l| l.contains("foo::outer")).expect(&s);
+        assert!(outer > inner, "{}", s);
+        assert!(lines[outer].starts_with("      "), "{}", s);
+        assert!(!lines[outer].contains("(inlined)"), "{}", s);
+        let main = lines.iter().position(|l| l.contains("foo::main")).expect(&s);
+        assert!(lines[main].starts_with("   1: "), "{}", s);
+    }
+
+    #[test]
+    fn aligned_locations() {
+        use super::FrameFmt;
+        use crate::backtrace_rs::{BytesOrWideString, SymbolName};
+        use crate::ffi::c_void;
+        use crate::fmt;
+
+        struct Aligned(Option<usize>, bool);
+        impl fmt::Display for Aligned {
+            fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let mut f = FrameFmt::new(fmt, PrintFmt::Full, false, None, &[]);
+                f.align = self.0;
+                let file = Some(BytesOrWideString::Bytes(b"/src/foo.rs")).filter(|_| self.1);
+                f.print_raw(
+                    0x1010 as *mut c_void,
+                    false,
+                    Some(SymbolName::new(b"foo::bar")),
+                    Some(0x1000 as *mut c_void),
+                    file,
+                    Some(3),
+                )
+            }
+        }
+        let s = Aligned(Some(60), true).to_string();
+        let line = s.lines().last().unwrap();
+        assert_eq!(line.find("at /src/foo.rs:3"), Some(60), "{}", s);
+        assert!(line.contains("foo::bar+0x10 "), "{}", s);
+
+        // Locations of long lines follow them after a space.
+        let s = Aligned(Some(10), true).to_string();
+        assert!(s.ends_with("foo::bar+0x10 at /src/foo.rs:3\n"), "{}", s);
+
+        // Without a location or alignment frames are printed as before.
+        let s = Aligned(Some(60), false).to_string();
+        assert!(s.ends_with("foo::bar+0x10\n"), "{}", s);
+        let s = Aligned(None, true).to_string();
+        assert!(s.contains("foo::bar+0x10\n"), "{}", s);
+        assert_eq!(s.lines().last().unwrap().trim_start(), "at /src/foo.rs:3", "{}", s);
+    }
+
+    #[test]
+    fn unresolved_frame_numbering() {
+        let unresolved = |ip| SyntheticFrame {
+            ip,
+            addr: None,
+            name: None,
+            filename: None,
+            lineno: None,
+            inlined: Vec::new(),
+        };
+        for &print_fmt in &[PrintFmt::Short, PrintFmt::Full] {
+            let frames = vec![
+                unresolved(0x10),
+                frame(0x20, "foo::inner", None, 3),
+                unresolved(0),
+                unresolved(0x30),
+                frame(0x40, "foo::outer", None, 7),
+            ];
+            let s = format_synthetic(frames, print_fmt, OutputStyle::Text);
+            let indices: Vec<&str> = s
+                .lines()
+                .filter_map(|l| l.split(':').next())
+                .filter(|n| n.starts_with(' ') && n.trim().parse::<usize>().is_ok())
+                .map(|n| n.trim())
+                .collect();
+            // Null frames are left out of short backtraces entirely.
+            let expected: &[&str] = match print_fmt {
+                PrintFmt::Short => &["0", "1", "2", "3"],
+                _ => &["0", "1", "2", "3", "4"],
+            };
+            assert_eq!(indices, expected, "{}", s);
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn unresolved_module_offset() {
+        let ip = unresolved_module_offset as usize;
+        let unresolved = SyntheticFrame {
+            ip,
+            addr: None,
+            name: None,
+            filename: None,
+            lineno: None,
+            inlined: Vec::new(),
+        };
+        let frames = vec![frame(ip, "foo::inner", None, 3), unresolved];
+        let s = format_synthetic(frames, PrintFmt::Full, OutputStyle::Text);
+        let lines: Vec<&str> = s.lines().filter(|l| l.contains(" - ")).collect();
+        assert!(!lines[0].contains("+0x"), "{}", s);
+        assert!(lines[1].contains("+0x") && lines[1].contains(") - "), "{}", s);
+    }
+
+    #[test]
+    fn synthetic_short_trimming() {
+        let frames = vec![
+            frame(0x10, "foo::inner", None, 3),
+            frame(0x20, "std::sys_common::backtrace::__rust_begin_short_backtrace", None, 1),
+            frame(0x30, "std::rt::lang_start", None, 2),
+        ];
+        let s = format_synthetic(frames, PrintFmt::Short, OutputStyle::Text);
+        assert!(s.contains("   0: foo::inner"), "{}", s);
+        assert!(!s.contains("lang_start"), "{}", s);
+        assert!(!s.contains("note: backtrace contained"), "{}", s);
+        assert!(s.contains("   0: foo::inner\n      ... 2 runtime frames hidden ...\n"), "{}", s);
+        assert!(!s.contains("panic originated here"), "{}", s);
+
+        let frames = vec![
+            frame(0x10, "foo::inner", None, 3),
+            frame(0x20, "std::sys_common::backtrace::__rust_begin_short_backtrace", None, 1),
+            frame(0x30, "std::rt::lang_start", None, 2),
+        ];
+        let s = format_synthetic(frames, PrintFmt::Short, OutputStyle::Compact);
+        assert_eq!(s, "foo::inner\n");
+    }
+
+    #[test]
+    fn custom_frame_formatter() {
+        use crate::backtrace::{set_frame_formatter, take_frame_formatter};
+
+        set_frame_formatter(|fmt, frame| {
+            writeln!(fmt, "<{:?}|{}>", frame.symbol_name(), frame.lineno().unwrap_or(0))
+        });
+        let frames = vec![frame(0x10, "foo::inner", None, 3), frame(0x20, "foo::outer", None, 7)];
+        let s = format_synthetic(frames, PrintFmt::Full, OutputStyle::Text);
+        assert!(s.contains("\n<Some(\"foo::inner\")|3>\n<Some(\"foo::outer\")|7>\n"), "{}", s);
+        assert!(!s.contains("0x10"), "{}", s);
+
+        assert!(take_frame_formatter().is_some());
+        let frames = vec![frame(0x10, "foo::inner", None, 3)];
+        let s = format_synthetic(frames, PrintFmt::Full, OutputStyle::Text);
+        assert!(s.contains("0x10 - foo::inner"), "{}", s);
+    }
+
+    #[test]
+    fn panic_origin() {
+        let frames = vec![
+            frame(0x10, "std::panicking::begin_panic_fmt", None, 1),
+            frame(0x20, "core::panicking::panic", None, 2),
+            frame(0x30, "core::option::Option<T>::unwrap", None, 3),
+            frame(0x40, "foo::inner", None, 4),
+            frame(0x50, "foo::outer", None, 5),
+        ];
+        let s = format_synthetic(frames, PrintFmt::Short, OutputStyle::Text);
+        assert!(s.contains("   3: foo::inner <- panic originated here\n"), "{}", s);
+        assert_eq!(s.matches("panic originated here").count(), 1, "{}", s);
+    }
+
+    #[test]
+    fn catch_boundary() {
+        let frames = vec![
+            frame(0x10, "foo::inner", None, 1),
+            frame(0x20, "std::panicking::try::do_call", None, 2),
+            frame(0x30, "__rust_try", None, 3),
+            frame(0x40, "std::panicking::try", None, 4),
+            frame(0x50, "std::panic::catch_unwind", None, 5),
+            frame(0x60, "std::panicking::try", None, 6),
+        ];
+        for &print_fmt in &[PrintFmt::Short, PrintFmt::Full] {
+            let s = format_synthetic(frames.clone(), print_fmt, OutputStyle::Text);
+            assert!(s.contains("__rust_try <- caught here (catch_unwind)\n"), "{}", s);
+            assert_eq!(s.matches("caught here").count(), 1, "{}", s);
+            assert!(!s.contains("panic originated here"), "{}", s);
+        }
+
+        // Real symbols are mangled.
+        use super::is_catch_symbol;
+        use crate::backtrace_rs::SymbolName;
+
+        let catches = |name: &[u8]| is_catch_symbol(&SymbolName::new(name));
+        assert!(catches(b"_ZN3std9panicking3try17h6b3a18e0c5f9d2a1E"));
+        assert!(catches(b"std::panicking::try::<(), F>"));
+        assert!(catches(b"std::panicking::try::h6b3a18e0c5f9d2a1"));
+        assert!(catches(b"__rust_maybe_catch_panic"));
+        assert!(!catches(b"_ZN3std9panicking3try7do_call17h6b3a18e0c5f9d2a1E"));
+        assert!(!catches(b"_ZN3std9panicking9try_again17h6b3a18e0c5f9d2a1E"));
+    }
+
+    #[test]
+    fn executor_boundary() {
+        use super::{ExecutorBoundary, ResolvedSymbol};
+
+        let symbol = |name: &str| ResolvedSymbol {
+            name: Some(name.as_bytes().to_vec()),
+            addr: None,
+            filename: None,
+            lineno: None,
+        };
+        let symbols = [
+            symbol("foo::handler::{{closure}}"),
+            symbol("<std::future::GenFuture<T> as core::future::future::Future>::poll"),
+            symbol("tokio::runtime::basic_scheduler::BasicScheduler<P>::block_on"),
+            symbol("tokio::runtime::Runtime::block_on"),
+            symbol("foo::main"),
+        ];
+        let boundaries = |patterns: &[&str]| {
+            let patterns: Vec<String> = patterns.iter().map(|&p| p.into()).collect();
+            let mut executor = ExecutorBoundary::new(&patterns);
+            let found: Vec<bool> = symbols.iter().map(|s| executor.is_boundary(Some(s))).collect();
+            assert!(!executor.is_boundary(None));
+            found
+        };
+
+        // The line is drawn once, above the innermost frame of the executor.
+        assert_eq!(boundaries(&["tokio::runtime::"]), [false, false, true, false, false]);
+        assert_eq!(boundaries(&["foo::main", "Runtime::"]), [false, false, false, true, false]);
+        assert_eq!(boundaries(&[]), [false; 5]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn filename_to_path() {
+        use super::BytesOrWide;
+        use crate::os::unix::prelude::*;
+
+        // Paths on Unix aren't necessarily UTF-8, and are kept as they are.
+        let bytes = b"/src/\xfflib.rs".to_vec();
+        let filename = BytesOrWide::Bytes(bytes.clone());
+        assert_eq!(filename.to_path().as_os_str().as_bytes(), &bytes[..]);
+        assert_eq!(BytesOrWide::Wide(vec![0x61]).to_path().to_str(), Some("<unknown>"));
+    }
+
+    #[test]
+    fn short_backtrace_end() {
+        use super::is_short_backtrace_end;
+        use crate::backtrace_rs::SymbolName;
+
+        let end = |name: &str| is_short_backtrace_end(&SymbolName::new(name.as_bytes()));
+        assert!(end("std::sys_common::backtrace::__rust_begin_short_backtrace"));
+        assert!(end("test::__rust_begin_short_backtrace"));
+        assert!(end("std::sys_common::backtrace::__rust_begin_short_backtrace::<F, T>"));
+        assert!(!end("foo::__rust_begin_short_backtrace_wrapper"));
+        assert!(!end("foo::my__rust_begin_short_backtrace"));
+        assert!(end("std::rt::lang_start"));
+        assert!(end("std::rt::lang_start::{{closure}}"));
+        assert!(end("std::rt::lang_start_internal"));
+        assert!(!end("foo::main"));
+    }
+
+    #[test]
+    fn truncate_symbols() {
+        let name = "<futures::future::Map<Fut, F> as core::future::Future>::poll";
+        assert_eq!(truncate_symbol(name, 100), name);
+        assert_eq!(truncate_symbol(name, name.len()), name);
+        assert_eq!(truncate_symbol(name, 20), "<futures::future::M…");
+        assert_eq!(truncate_symbol(name, 20).chars().count(), 20);
+        assert_eq!(truncate_symbol("äöüäöü", 4), "äöü…");
+        assert_eq!(truncate_symbol("foo", 1), "…");
+    }
+
+    #[test]
+    fn short_backtrace_guards() {
+        use super::{pop_short_backtrace_guard, push_short_backtrace_guard};
+
+        let frames = vec![
+            frame(0x10, "foo::inner", None, 3),
+            SyntheticFrame { addr: Some(0x2000), ..frame(0x20, "harness::run_test", None, 1) },
+            frame(0x30, "harness::run_tests", None, 2),
+            SyntheticFrame { addr: Some(0x4000), ..frame(0x40, "harness::main", None, 3) },
+        ];
+        let outer = push_short_backtrace_guard(0x4000);
+        let s = format_synthetic(frames.clone(), PrintFmt::Short, OutputStyle::Compact);
+        assert_eq!(s, "harness::run_tests <- harness::run_test <- foo::inner\n");
+
+        // Only the innermost guard is trimmed at.
+        let inner = push_short_backtrace_guard(0x2000);
+        let s = format_synthetic(frames.clone(), PrintFmt::Short, OutputStyle::Compact);
+        assert_eq!(s, "foo::inner\n");
+        let s = format_synthetic(frames.clone(), PrintFmt::Short, OutputStyle::Addrs);
+        assert_eq!(s, "0x10\n");
+        let s = format_synthetic(frames.clone(), PrintFmt::Full, OutputStyle::Addrs);
+        assert_eq!(s, "0x10\n0x20\n0x30\n0x40\n");
+
+        pop_short_backtrace_guard(inner);
+        let s = format_synthetic(frames.clone(), PrintFmt::Short, OutputStyle::Compact);
+        assert_eq!(s, "harness::run_tests <- harness::run_test <- foo::inner\n");
+        pop_short_backtrace_guard(outer);
+        let s = format_synthetic(frames, PrintFmt::Short, OutputStyle::Compact);
+        let all = "harness::main <- harness::run_tests <- harness::run_test <- foo::inner\n";
+        assert_eq!(s, all);
+    }
+
+    #[test]
+    fn truncation_notes() {
+        use super::{MAX_NB_FRAMES, SHORT_BACKTRACE_SENTINEL};
+
+        let capped = "note: reached frame limit";
+        let trimmed = "note: Some details are omitted, \
+                       run with `RUST_BACKTRACE=full` for a verbose backtrace.";
+        let summary = "note: backtrace contained";
+        let frames = (0..MAX_NB_FRAMES + 1)
+            .map(|i| frame(0x10 + i, &format!("foo::f{}", i), None, 3))
+            .collect();
+        let s = format_synthetic(frames, PrintFmt::Short, OutputStyle::Text);
+        let note = format!("{} ({}); increase `RUST_BACKTRACE_LIMIT`", capped, MAX_NB_FRAMES);
+        assert!(s.contains(&note), "{}", s);
+        assert!(s.contains(trimmed), "{}", s);
+        let count = format!("{} {} frames ({} shown)", summary, MAX_NB_FRAMES + 1, MAX_NB_FRAMES);
+        assert!(s.contains(&count), "{}", s);
+
+        let frames = vec![
+            frame(0x10, "foo::inner", None, 3),
+            frame(0x20, &format!("std::rt::{}", SHORT_BACKTRACE_SENTINEL), None, 1),
+            frame(0x30, "std::rt::lang_start", None, 2),
+        ];
+        let s = format_synthetic(frames.clone(), PrintFmt::Short, OutputStyle::Text);
+        assert!(s.ends_with(&format!("{}\n", trimmed)), "{}", s);
+        assert!(!s.contains(capped) && !s.contains(summary), "{}", s);
+
+        let s = format_synthetic(frames, PrintFmt::Full, OutputStyle::Text);
+        assert!(!s.contains(trimmed) && !s.contains(capped), "{}", s);
+    }
+
+    #[test]
+    fn registered_sentinel() {
+        let sentinel = 0x1234_5678;
+        super::add_short_backtrace_sentinel(sentinel);
+        let frames = vec![
+            frame(0x10, "foo::inner", None, 3),
+            SyntheticFrame { addr: Some(sentinel), ..frame(0x20, "executor::run", None, 1) },
+            frame(0x30, "executor::internals", None, 2),
+        ];
+        let s = format_synthetic(frames, PrintFmt::Short, OutputStyle::Compact);
+        assert_eq!(s, "foo::inner\n");
+    }
+
+    #[test]
+    fn begin_short_backtrace_frame() {
+        use super::{__rust_begin_short_backtrace, __rust_begin_short_backtrace_frame};
+
+        assert_eq!(__rust_begin_short_backtrace(|| 7), 7);
+        // The frame is known by its address whatever it resolves to.
+        let sentinel = __rust_begin_short_backtrace_frame as usize;
+        let frames = vec![
+            frame(0x10, "foo::inner", None, 3),
+            SyntheticFrame { addr: Some(sentinel), ..frame(0x20, "<unknown>", None, 1) },
+            frame(0x30, "std::rt::lang_start", None, 2),
+        ];
+        let s = format_synthetic(frames, PrintFmt::Short, OutputStyle::Compact);
+        assert_eq!(s, "foo::inner\n");
+    }
+
+    #[test]
+    fn synthetic_addrs() {
+        let sentinel = 0x8765_4321;
+        super::add_short_backtrace_sentinel(sentinel);
+        let frames = vec![
+            frame(0x10, "foo::inner", None, 3),
+            frame(0, "<null>", None, 0),
+            frame(0xabc, "foo::outer", None, 4),
+            SyntheticFrame { addr: Some(sentinel), ..frame(0x20, "executor::run", None, 1) },
+            frame(0x30, "executor::internals", None, 2),
+        ];
+        let s = format_synthetic(frames.clone(), PrintFmt::Short, OutputStyle::Addrs);
+        assert_eq!(s, "0x10\n0xabc\n");
+        let s = format_synthetic(frames, PrintFmt::Full, OutputStyle::Addrs);
+        assert_eq!(s, "0x10\n0x0\n0xabc\n0x20\n0x30\n");
+    }
+
+    #[test]
+    fn synthetic_without_current_dir() {
+        let cwd = env::current_dir().unwrap();
+        let file = cwd.join("src").join("lib.rs");
+        let frames = vec![
+            frame(0x10, "foo::inner", Some(file.to_str().unwrap()), 3),
+            frame(0x20, "foo::outer", Some(file.to_str().unwrap()), 4),
+        ];
+        // Like a sandbox denying access to the current directory would.
+        super::CURRENT_DIR.with(|dir| *dir.borrow_mut() = Some(None));
+        let short = format_synthetic(frames.clone(), PrintFmt::Short, OutputStyle::Text);
+        let full = format_synthetic(frames, PrintFmt::Full, OutputStyle::Text);
+        super::CURRENT_DIR.with(|dir| *dir.borrow_mut() = None);
+
+        for s in &[short, full] {
+            assert!(s.contains("foo::inner") && s.contains("foo::outer"), "{}", s);
+            assert!(s.contains(&format!("at {}:3", file.display())), "{}", s);
+            assert!(s.contains(&format!("at {}:4", file.display())), "{}", s);
+        }
+    }
+
+    #[test]
+    fn synthetic_overflowed() {
+        let frames = vec![frame(0x10, "foo::inner", None, 3)];
+        let note = "note: stack overflowed; backtrace may be incomplete near the top\n";
+        let plain = format_synthetic(frames.clone(), PrintFmt::Short, OutputStyle::Text);
+        assert!(!plain.contains(note));
+
+        let mut buf = Vec::new();
+        let source = FrameSource::Synthetic(frames);
+        unsafe {
+            _print(&mut buf, &source, PrintFmt::Short, OutputStyle::Text, false, true).unwrap();
+        }
+        let s = String::from_utf8(buf).unwrap();
+        assert_eq!(s, format!("{}{}", note, plain));
+    }
+
+    #[test]
+    fn synthetic_line_hook() {
+        use crate::backtrace::{set_line_hook, take_line_hook};
+        use crate::borrow::Cow;
+
+        let frames = vec![frame(0x10, "foo::inner", None, 3), frame(0x20, "foo::outer", None, 4)];
+        let plain = format_synthetic(frames.clone(), PrintFmt::Short, OutputStyle::Text);
+
+        set_line_hook(|line| Cow::Owned(format!("<{}>", line)));
+        let s = format_synthetic(frames.clone(), PrintFmt::Short, OutputStyle::Text);
+        let expected: String = plain.lines().map(|line| format!("<{}>\n", line)).collect();
+        assert_eq!(s, expected);
+
+        // A panicking hook leaves the line as it is.
+        set_line_hook(|line| {
+            if line.contains("foo::outer") {
+                panic!("hook failed");
+            }
+            Cow::Borrowed(line)
+        });
+        let s = format_synthetic(frames.clone(), PrintFmt::Short, OutputStyle::Text);
+        assert_eq!(s, plain);
+
+        assert!(take_line_hook().is_some());
+        assert_eq!(format_synthetic(frames, PrintFmt::Short, OutputStyle::Text), plain);
+    }
+
+    #[test]
+    fn synthetic_no_symbols() {
+        let note = "note: no symbol information found";
+        let unnamed = SyntheticFrame { name: None, ..frame(0x10, "", None, 0) };
+        let frames = vec![unnamed.clone(), SyntheticFrame { ip: 0x20, ..unnamed }];
+        let s = format_synthetic(frames.clone(), PrintFmt::Short, OutputStyle::Text);
+        assert!(s.contains(note), "{}", s);
+
+        let named = vec![frames[0].clone(), frame(0x30, "foo::outer", None, 4)];
+        let s = format_synthetic(named, PrintFmt::Short, OutputStyle::Text);
+        assert!(!s.contains(note), "{}", s);
+    }
+
+    #[test]
+    fn synthetic_relative_paths() {
+        let cwd = env::current_dir().unwrap();
+        let file = cwd.join("src").join("lib.rs");
+        let frames = vec![frame(0x10, "foo::inner", Some(file.to_str().unwrap()), 3)];
+        let s = format_synthetic(frames, PrintFmt::Short, OutputStyle::Text);
+        let relative = format!("at .{0}src{0}lib.rs:3", crate::path::MAIN_SEPARATOR);
+        assert!(s.contains(&relative), "{}", s);
+    }
+
+    #[test]
+    fn line_prefix() {
+        use super::LinePrefix;
+        use crate::fmt::{self, Write};
+
+        struct Lines(&'static [&'static str]);
+
+        impl fmt::Display for Lines {
+            fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let mut fmt = LinePrefix::new(fmt, "[app] ");
+                for part in self.0 {
+                    fmt.write_str(part)?;
+                }
+                Ok(())
+            }
+        }
+
+        assert_eq!(Lines(&["stack backtrace:\n", "  0: foo\n"]).to_string(),
+                   "[app] stack backtrace:\n[app]   0: foo\n");
+        assert_eq!(Lines(&["  0: ", "foo\n     ", "at lib.rs\n\nnote"]).to_string(),
+                   "[app]   0: foo\n[app]      at lib.rs\n[app] \n[app] note");
+        assert_eq!(Lines(&[]).to_string(), "");
+    }
+
+    #[test]
+    fn buffered_print() {
+        use super::{BufferedPrint, PRINT_BUFFER_SIZE};
+        use crate::io::{self, Write};
+
+        // Accepts a few bytes at a time, remembering what each call was given.
+        struct Slow(Vec<u8>, Vec<Vec<u8>>);
+
+        impl Write for Slow {
+            fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+                let n = data.len().min(3);
+                self.0.extend_from_slice(&data[..n]);
+                self.1.push(data.to_vec());
+                Ok(n)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut slow = Slow(Vec::new(), Vec::new());
+        let line = "  1: foo::bar\n             at ./src/lib.rs:3\n";
+        let mut w = BufferedPrint::new(&mut slow, false);
+        for _ in 0..PRINT_BUFFER_SIZE / line.len() * 3 {
+            write!(w, "{}", line).unwrap();
+        }
+        write!(w, "end").unwrap();
+        w.finish().unwrap();
+
+        let expected = format!("{}end", line.repeat(PRINT_BUFFER_SIZE / line.len() * 3));
+        assert_eq!(slow.0, expected.as_bytes());
+        // The backtrace was written out in chunks of whole lines.
+        assert!(slow.1[0].len() >= PRINT_BUFFER_SIZE - line.len());
+        assert!(slow.1.iter().all(|data| data.ends_with(b"\n") || data.ends_with(b"end")));
+
+        // Errors are reported rather than skipped over.
+        struct Full;
+
+        impl Write for Full {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Ok(0)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut full = Full;
+        let mut w = BufferedPrint::new(&mut full, false);
+        write!(w, "{}", line).unwrap();
+        assert_eq!(w.finish().unwrap_err().kind(), io::ErrorKind::WriteZero);
+
+        // Lines are flushed as soon as they are complete if asked to.
+        struct Flushed {
+            data: Vec<u8>,
+            flushed: usize,
+        }
+
+        impl Write for Flushed {
+            fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+                self.data.extend_from_slice(data);
+                Ok(data.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                self.flushed = self.data.len();
+                Ok(())
+            }
+        }
+
+        let mut flushed = Flushed { data: Vec::new(), flushed: 0 };
+        let mut w = BufferedPrint::new(&mut flushed, true);
+        write!(w, "{}  2: foo", line).unwrap();
+        drop(w);
+        assert_eq!(flushed.data, line.as_bytes());
+        assert_eq!(flushed.flushed, line.len());
     }
-    fmt::Display::fmt(&file.display(), fmt)
 }
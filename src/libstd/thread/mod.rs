@@ -1064,6 +1064,12 @@ impl ThreadId {
             ThreadId(NonZeroU64::new(id).unwrap())
         }
     }
+
+    // Returns the numeric value of this ID, for diagnostics printed by the
+    // runtime itself.
+    pub(crate) fn as_u64(&self) -> u64 {
+        self.0.get()
+    }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -0,0 +1,47 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-cloudabi spawning processes is not supported
+// ignore-emscripten spawning processes is not supported
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no processes
+// ignore-windows paths aren't rooted at `/`
+// compile-flags:-g
+
+use std::env;
+use std::process::Command;
+use std::str;
+
+#[inline(never)]
+fn foo() {
+    panic!("absolute");
+}
+
+fn run(me: &str, absolute: &str) -> String {
+    // With the root as the working directory every path is relative to it.
+    let out = Command::new(me)
+        .arg("fail")
+        .current_dir("/")
+        .env("RUST_BACKTRACE", "1")
+        .env("RUST_BACKTRACE_ABSOLUTE_PATHS", absolute)
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    str::from_utf8(&out.stderr).unwrap().to_string()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 && args[1] == "fail" {
+        foo();
+        return;
+    }
+    let me = env::current_exe().unwrap();
+    let me = me.to_str().unwrap();
+
+    let s = run(me, "0");
+    assert!(s.contains(" at ./"), "paths not stripped: {}", s);
+
+    let s = run(me, "1");
+    assert!(s.contains(" at /"), "no absolute paths: {}", s);
+    assert!(!s.contains(" at ./"), "paths stripped: {}", s);
+}
@@ -0,0 +1,70 @@
+// run-pass
+// only-linux
+// compile-flags:-g
+
+#![feature(backtrace)]
+
+use std::backtrace::print_all_threads;
+use std::env;
+use std::process::Command;
+use std::str;
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+#[inline(never)]
+fn wait(barrier: &Barrier) {
+    barrier.wait();
+    // Prevent tail call optimization.
+    let _v = vec![1];
+}
+
+fn dump() -> String {
+    let started = Arc::new(Barrier::new(4));
+    let finish = Arc::new(Barrier::new(4));
+    let workers: Vec<_> = (0..3)
+        .map(|_| {
+            let started = started.clone();
+            let finish = finish.clone();
+            thread::spawn(move || {
+                started.wait();
+                wait(&finish);
+            })
+        })
+        .collect();
+    started.wait();
+    // Give the workers time to block in `wait`.
+    thread::sleep(std::time::Duration::from_millis(100));
+
+    let mut out = Vec::new();
+    print_all_threads(&mut out).unwrap();
+
+    finish.wait();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    String::from_utf8(out).unwrap()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 && args[1] == "default" {
+        // The other threads aren't interrupted unless asked to.
+        let s = dump();
+        assert!(!s.contains("backtrace_all_threads::wait"), "bad output: {}", s);
+        assert_eq!(s.matches("RUST_BACKTRACE_INTERRUPT_THREADS=1").count(), 3, "{}", s);
+        assert!(s.contains("backtrace_all_threads::main"), "bad output: {}", s);
+        return;
+    }
+    let mut cmd = Command::new(&args[0]);
+    let out = cmd.arg("default").env_remove("RUST_BACKTRACE_INTERRUPT_THREADS").output().unwrap();
+    assert!(out.status.success(), "{}", str::from_utf8(&out.stderr).unwrap());
+
+    env::set_var("RUST_BACKTRACE_INTERRUPT_THREADS", "1");
+    let s = &dump();
+
+    // The workers share a stack, which is printed once for all of them.
+    assert_eq!(s.matches("backtrace_all_threads::wait").count(), 1, "bad output: {}", s);
+    let shared = s.lines().find(|l| l.starts_with("threads ")).expect(s);
+    assert_eq!(shared.split(", ").count(), 3, "bad output: {}", s);
+    assert!(s.contains("backtrace_all_threads::main"), "bad output: {}", s);
+}
@@ -0,0 +1,92 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-cloudabi spawning processes is not supported
+// ignore-emscripten spawning processes is not supported
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no processes
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g
+// edition:2018
+
+use std::env;
+use std::future::Future;
+use std::pin::Pin;
+use std::process::Command;
+use std::ptr;
+use std::str;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+mod executor {
+    use super::*;
+
+    fn clone_waker(data: *const ()) -> RawWaker {
+        RawWaker::new(data, &RawWakerVTable::new(clone_waker, drop, drop, drop))
+    }
+
+    #[inline(never)]
+    pub fn block_on<F: Future>(mut future: F) -> F::Output {
+        let waker = unsafe { Waker::from_raw(clone_waker(ptr::null())) };
+        let mut cx = Context::from_waker(&waker);
+        let mut future = unsafe { Pin::new_unchecked(&mut future) };
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+}
+
+#[inline(never)]
+async fn handler() {
+    panic!("async");
+}
+
+fn run(me: &str, envs: &[(&str, &str)]) -> String {
+    let mut cmd = Command::new(me);
+    cmd.arg("fail").env("RUST_BACKTRACE", "1");
+    for &(key, value) in envs {
+        cmd.env(key, value);
+    }
+    let out = cmd.output().unwrap();
+    assert!(!out.status.success());
+    str::from_utf8(&out.stderr).unwrap().to_string()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 && args[1] == "fail" {
+        executor::block_on(handler());
+        return;
+    }
+
+    // The line is drawn between the future and the executor polling it, in
+    // either order.
+    for reverse in &["0", "1"] {
+        let envs = [
+            ("RUST_BACKTRACE_ASYNC", "1"),
+            ("RUST_BACKTRACE_EXECUTORS", "nope, backtrace_async::executor::"),
+            ("RUST_BACKTRACE_REVERSE", *reverse),
+        ];
+        let s = run(&args[0], &envs);
+        assert_eq!(s.matches("--- executor boundary ---").count(), 1, "bad output: {}", s);
+        let boundary = s.find("--- executor boundary ---").unwrap();
+        let handler = s.find("backtrace_async::handler").expect(&s);
+        let block_on = s.find("backtrace_async::executor::block_on").expect(&s);
+        if *reverse == "0" {
+            assert!(handler < boundary && boundary < block_on, "bad order: {}", s);
+        } else {
+            assert!(block_on < boundary && boundary < handler, "bad order: {}", s);
+        }
+    }
+
+    // Executors which aren't well known aren't recognized unless configured,
+    // even if they have a function named like those of runtimes.
+    let s = run(&args[0], &[("RUST_BACKTRACE_ASYNC", "1")]);
+    assert!(!s.contains("executor boundary"), "bad output: {}", s);
+    let envs = [("RUST_BACKTRACE_ASYNC", "1"), ("RUST_BACKTRACE_EXECUTORS", "executor::")];
+    assert!(run(&args[0], &envs).contains("--- executor boundary ---"));
+
+    // Nothing is drawn unless enabled.
+    let s = run(&args[0], &[]);
+    assert!(!s.contains("executor boundary"), "bad output: {}", s);
+}
@@ -0,0 +1,35 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-emscripten no backtrace support
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no symbolization
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g
+
+#![feature(backtrace)]
+
+use std::backtrace::Backtrace;
+use std::env;
+
+#[inline(never)]
+fn capture(depth: usize) -> Backtrace {
+    Backtrace::capture_with_depth(depth)
+}
+
+#[inline(never)]
+fn nested(n: usize, depth: usize) -> Backtrace {
+    if n == 0 { capture(depth) } else { nested(n - 1, depth) }
+}
+
+fn main() {
+    env::set_var("RUST_LIB_BACKTRACE", "1");
+
+    let all = nested(10, usize::max_value());
+    assert!(all.frames().len() > 10, "too few frames: {:?}", all);
+
+    let bt = nested(10, 3);
+    let frames = bt.frames();
+    assert_eq!(frames.len(), 3, "bad depth: {:?}", frames);
+    let name = frames[0].symbol_name().unwrap();
+    assert_eq!(name, "backtrace_capture_depth::capture", "bad frames: {:?}", frames);
+}
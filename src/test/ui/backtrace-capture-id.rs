@@ -0,0 +1,36 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-emscripten no backtrace support
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no symbolization
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g
+
+#![feature(backtrace)]
+
+use std::backtrace::Backtrace;
+use std::thread;
+
+fn main() {
+    let first = Backtrace::force_capture();
+    let second = thread::spawn(Backtrace::force_capture).join().unwrap();
+    let third = Backtrace::force_capture();
+
+    // Backtraces are numbered in the order they were captured in.
+    let (a, b, c) = (first.id().unwrap(), second.id().unwrap(), third.id().unwrap());
+    assert!(a < b && b < c, "bad ids: {} {} {}", a, b, c);
+    let (a, b, c) = (first.captured_at(), second.captured_at(), third.captured_at());
+    assert!(a.unwrap() <= b.unwrap() && b.unwrap() <= c.unwrap());
+
+    // A chained backtrace has a number of its own, but keeps the time of its
+    // innermost backtrace.
+    let chained = third.chain(&first);
+    assert!(chained.id().unwrap() > third.id().unwrap());
+    assert_eq!(chained.captured_at(), third.captured_at());
+
+    std::env::remove_var("RUST_BACKTRACE");
+    std::env::remove_var("RUST_LIB_BACKTRACE");
+    let disabled = Backtrace::capture();
+    assert_eq!(disabled.id(), None);
+    assert_eq!(disabled.captured_at(), None);
+}
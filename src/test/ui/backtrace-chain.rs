@@ -0,0 +1,59 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-emscripten no backtrace support
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no symbolization
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g
+
+#![feature(backtrace)]
+
+use std::backtrace::Backtrace;
+use std::thread;
+
+#[inline(never)]
+fn spawn() -> Backtrace {
+    Backtrace::force_capture()
+}
+
+#[inline(never)]
+fn poll() -> Backtrace {
+    Backtrace::force_capture()
+}
+
+// Returns the indices of the frames printed in `s`, in order.
+fn indices(s: &str) -> Vec<usize> {
+    s.lines().filter_map(|l| l.split(':').next()?.trim().parse().ok()).collect()
+}
+
+fn main() {
+    let spawned = spawn();
+    let polled = thread::spawn(poll).join().unwrap();
+    let bt = polled.chain(&spawned);
+    assert_eq!(bt.len(), polled.len() + spawned.len());
+
+    // Nothing is borrowed from either backtrace.
+    drop(polled);
+    drop(spawned);
+    let s = thread::spawn(move || bt.to_string()).join().unwrap();
+    let separator = s.find("--- spawned at ---").expect(&s);
+    let poll = s.find("backtrace_chain::poll").expect(&s);
+    let spawn = s.find("backtrace_chain::spawn").expect(&s);
+    assert!(poll < separator && separator < spawn, "bad order: {}", s);
+
+    // Each backtrace is trimmed separately, like the backtrace of a panic,
+    // which it ends with the notes of as well.
+    let hidden = s.find("runtime frames hidden").expect(&s);
+    assert!(poll < hidden && hidden < separator, "bad order: {}", s);
+    assert!(s.ends_with("for a verbose backtrace.\n"), "missing note: {}", s);
+
+    // The frames are numbered on across both backtraces.
+    let indices = indices(&s);
+    assert!(indices.iter().enumerate().all(|(i, &n)| i == n), "bad numbering: {}", s);
+
+    std::env::remove_var("RUST_BACKTRACE");
+    std::env::remove_var("RUST_LIB_BACKTRACE");
+    let disabled = Backtrace::capture();
+    assert!(disabled.chain(&disabled).is_empty());
+    assert_eq!(disabled.chain(&disabled).to_string(), "<backtrace disabled>");
+}
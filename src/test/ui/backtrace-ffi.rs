@@ -0,0 +1,39 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-cloudabi spawning processes is not supported
+// ignore-emscripten spawning processes is not supported
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no processes
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g
+
+use std::env;
+use std::process::Command;
+use std::str;
+
+// Declared the way C code linked into the program would.
+extern "C" {
+    fn rust_print_backtrace();
+}
+
+#[inline(never)]
+fn dump() {
+    unsafe { rust_print_backtrace() }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 && args[1] == "dump" {
+        dump();
+        println!("still running");
+        return;
+    }
+
+    // Backtraces are printed even if `RUST_BACKTRACE` doesn't enable them.
+    let out = Command::new(&args[0]).arg("dump").env_remove("RUST_BACKTRACE").output().unwrap();
+    assert!(out.status.success());
+    assert_eq!(str::from_utf8(&out.stdout).unwrap(), "still running\n");
+    let s = str::from_utf8(&out.stderr).unwrap();
+    assert!(s.contains("stack backtrace:"), "bad output: {}", s);
+    assert!(s.contains("backtrace_ffi::dump"), "bad output: {}", s);
+}
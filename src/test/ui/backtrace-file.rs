@@ -0,0 +1,74 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-cloudabi spawning processes is not supported
+// ignore-emscripten spawning processes is not supported
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no processes
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::str;
+
+#[inline(never)]
+fn foo() {
+    panic!("to a file");
+}
+
+fn run(me: &str, file: &Path) -> String {
+    let out = Command::new(me)
+        .arg("fail")
+        .env("RUST_BACKTRACE", "1")
+        .env("RUST_BACKTRACE_FILE", file)
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    str::from_utf8(&out.stderr).unwrap().to_string()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 && args[1] == "fail" {
+        foo();
+        return;
+    }
+
+    let file = env::temp_dir().join(format!("backtrace-file-{}.txt", std::process::id()));
+    let _ = fs::remove_file(&file);
+
+    // Backtraces are appended, and still printed to stderr
+    let mut printed = Vec::new();
+    for _ in 0..2 {
+        let s = run(&args[0], &file);
+        assert!(s.contains("backtrace_file::foo"), "bad output: {}", s);
+        printed.push(s);
+    }
+    let contents = fs::read_to_string(&file).unwrap();
+    fs::remove_file(&file).unwrap();
+    assert_eq!(contents.matches("backtrace of process").count(), 2, "bad file: {}", contents);
+    assert_eq!(contents.matches("backtrace_file::foo").count(), 2, "bad file: {}", contents);
+
+    // Each backtrace is headed by the pid and the time, like
+    // `backtrace of process 42 at 2019-10-14T12:34:56Z`.
+    let pid = format!("backtrace of process {} at ", std::process::id());
+    for header in contents.lines().filter(|l| l.starts_with("backtrace of process")) {
+        assert!(!header.starts_with(&pid), "parent's pid: {}", header);
+        let time = header.rsplit(' ').next().unwrap().as_bytes();
+        assert_eq!(time.len(), 20, "bad header: {}", header);
+        assert!(time[4] == b'-' && time[10] == b'T' && time[19] == b'Z', "bad header: {}", header);
+    }
+
+    // The file gets exactly what was printed to stderr.
+    let copies = contents.split("backtrace of process ").skip(1);
+    for (copy, s) in copies.zip(&printed) {
+        let copy = &copy[copy.find('\n').unwrap() + 1..];
+        assert!(s.contains(copy), "bad copy: {}\nof: {}", copy, s);
+    }
+
+    // A file which can't be opened doesn't get in the way
+    let s = run(&args[0], &file.join("not-a-directory"));
+    assert!(s.contains("backtrace_file::foo"), "bad output: {}", s);
+}
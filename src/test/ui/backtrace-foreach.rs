@@ -0,0 +1,51 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-emscripten no backtrace support
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no symbolization
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g
+
+#![feature(backtrace)]
+
+use std::backtrace::{foreach_frame, FrameControl};
+
+#[inline(never)]
+fn collect(trim: bool) -> Vec<(String, Option<u32>)> {
+    let mut frames = Vec::new();
+    foreach_frame(trim, |frame| {
+        let name = frame.symbol_name().unwrap_or("<unknown>").to_string();
+        frames.push((name, frame.lineno()));
+        FrameControl::Continue
+    });
+    frames
+}
+
+#[inline(never)]
+fn find_main() -> usize {
+    let mut seen = 0;
+    foreach_frame(false, |frame| {
+        seen += 1;
+        if frame.symbol_name() == Some("backtrace_foreach::main") {
+            FrameControl::Stop
+        } else {
+            FrameControl::Continue
+        }
+    });
+    seen
+}
+
+fn main() {
+    let frames = collect(true);
+    let ours = frames.iter().find(|f| f.0 == "backtrace_foreach::collect").expect("no frame");
+    assert!(ours.1.is_some(), "no line number: {:?}", frames);
+    assert!(!frames.iter().any(|f| f.0.contains("lang_start")), "not trimmed: {:?}", frames);
+
+    let frames = collect(false);
+    assert!(frames.iter().any(|f| f.0 == "backtrace_foreach::collect"), "no frame: {:?}", frames);
+    assert!(frames.iter().any(|f| f.0.contains("lang_start")), "trimmed: {:?}", frames);
+
+    // Nothing is handed out after stopping at `main`.
+    let main = frames.iter().position(|f| f.0 == "backtrace_foreach::main").expect("no main");
+    assert_eq!(find_main(), main + 1, "frames: {:?}", frames);
+}
@@ -0,0 +1,28 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-emscripten no backtrace support
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no symbolization
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g
+
+#![feature(backtrace)]
+
+use std::backtrace::frame_count;
+
+#[inline(never)]
+fn recurse(n: usize, trim: bool) -> usize {
+    let count = if n == 0 { frame_count(trim) } else { recurse(n - 1, trim) };
+    // Prevent tail call optimization.
+    let _v = vec![n];
+    count
+}
+
+fn main() {
+    let shallow = recurse(0, false);
+    assert_eq!(recurse(10, false), shallow + 10);
+
+    let trimmed = recurse(0, true);
+    assert!(trimmed > 0 && trimmed < shallow, "{} frames, {} trimmed", shallow, trimmed);
+    assert_eq!(recurse(10, true), trimmed + 10);
+}
@@ -0,0 +1,50 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-emscripten no backtrace support
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no symbolization
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g
+
+#![feature(backtrace)]
+
+use std::backtrace::Backtrace;
+use std::thread;
+
+#[inline(never)]
+fn capture() -> Backtrace {
+    Backtrace::force_capture()
+}
+
+fn main() {
+    let bt = capture();
+    let frames = bt.frames();
+    let ours = frames
+        .iter()
+        .find(|f| f.symbol_name().as_ref().map(|s| &s[..]) == Some("backtrace_frames::capture"))
+        .expect("no frame");
+    assert!(!ours.ip().is_null());
+    assert!(ours.lineno().is_some(), "no line number: {:?}", frames);
+    let file = ours.filename().expect("no file");
+    assert!(file.ends_with("backtrace-frames.rs"), "bad file: {:?}", file);
+    assert!(!frames.iter().any(|f| f.symbol_name().map_or(false, |s| s.contains("force_capture"))),
+            "capturing frames kept: {:?}", frames);
+
+    // The resolved frames can be looked at from other threads as well.
+    let n = frames.len();
+    let bt = thread::spawn(move || {
+        assert_eq!(bt.frames().len(), n);
+        bt
+    }).join().unwrap();
+    assert_eq!(bt.frames().len(), n);
+    assert_eq!(bt.len(), n);
+    assert!(!bt.is_empty());
+
+    std::env::remove_var("RUST_BACKTRACE");
+    std::env::remove_var("RUST_LIB_BACKTRACE");
+    let bt = Backtrace::capture();
+    assert!(bt.frames().is_empty());
+    assert_eq!(bt.len(), 0);
+    assert!(bt.is_empty());
+    assert_eq!(bt.to_string(), "<backtrace disabled>");
+}
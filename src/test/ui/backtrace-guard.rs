@@ -0,0 +1,56 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-cloudabi spawning processes is not supported
+// ignore-emscripten spawning processes is not supported
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no processes
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g
+
+#![feature(backtrace)]
+
+use std::backtrace::ShortBacktraceGuard;
+use std::env;
+use std::process::Command;
+use std::str;
+
+#[inline(never)]
+fn run_test(guard: bool, test: &dyn Fn()) {
+    let _guard = if guard { Some(ShortBacktraceGuard::enter()) } else { None };
+    test();
+    // Prevent tail call optimization.
+    let _v = vec![1];
+}
+
+#[inline(never)]
+fn harness_internals(guard: bool) {
+    run_test(guard, &|| panic!("test failed"));
+    // Prevent tail call optimization.
+    let _v = vec![2];
+}
+
+fn run(me: &str, guard: bool) -> String {
+    let mut cmd = Command::new(me);
+    cmd.arg(if guard { "guard" } else { "plain" }).env("RUST_BACKTRACE", "1");
+    let out = cmd.output().unwrap();
+    assert!(!out.status.success());
+    str::from_utf8(&out.stderr).unwrap().to_string()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 {
+        harness_internals(args[1] == "guard");
+        return;
+    }
+
+    let s = run(&args[0], false);
+    assert!(s.contains("backtrace_guard::run_test"), "bad output: {}", s);
+    assert!(s.contains("backtrace_guard::harness_internals"), "bad output: {}", s);
+
+    // The test itself is still shown, the harness isn't.
+    let s = run(&args[0], true);
+    assert!(s.contains("backtrace_guard::harness_internals::{{closure}}"), "{}", s);
+    assert!(!s.contains("backtrace_guard::run_test"), "not trimmed: {}", s);
+    assert!(!s.contains("backtrace_guard::harness_internals\n"), "not trimmed: {}", s);
+}
@@ -0,0 +1,51 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-cloudabi spawning processes is not supported
+// ignore-emscripten spawning processes is not supported
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no processes
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g
+
+#![feature(backtrace)]
+
+use std::backtrace::backtrace_hash;
+use std::env;
+use std::process::Command;
+use std::str;
+
+#[inline(never)]
+fn foo() -> u64 {
+    let hash = backtrace_hash();
+    // Prevent tail call optimization.
+    let _v = vec![1];
+    hash
+}
+
+#[inline(never)]
+fn bar() -> u64 {
+    let hash = backtrace_hash();
+    // Prevent tail call optimization.
+    let _v = vec![2];
+    hash
+}
+
+fn run(me: &str, which: &str) -> String {
+    let out = Command::new(me).arg(which).output().unwrap();
+    assert!(out.status.success());
+    str::from_utf8(&out.stdout).unwrap().to_string()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 {
+        let hash = if args[1] == "foo" { foo() } else { bar() };
+        println!("{}", hash);
+        return;
+    }
+
+    assert_eq!(foo(), foo());
+    assert_eq!(run(&args[0], "foo"), run(&args[0], "foo"));
+    assert_eq!(run(&args[0], "foo"), foo().to_string() + "\n");
+    assert_ne!(run(&args[0], "foo"), run(&args[0], "bar"));
+}
@@ -0,0 +1,51 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-cloudabi spawning processes is not supported
+// ignore-emscripten spawning processes is not supported
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no processes
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g -Copt-level=1
+
+use std::env;
+use std::process::Command;
+use std::str;
+
+#[inline(always)]
+fn inner() {
+    panic!("inlined");
+}
+
+#[inline(never)]
+fn outer() {
+    inner();
+    // Prevent tail call optimization.
+    let _v = vec![1];
+}
+
+fn run(me: &str, backtrace: &str) -> String {
+    let out = Command::new(me).arg("fail").env("RUST_BACKTRACE", backtrace).output().unwrap();
+    assert!(!out.status.success());
+    str::from_utf8(&out.stderr).unwrap().to_string()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 && args[1] == "fail" {
+        outer();
+        return;
+    }
+
+    for backtrace in &["1", "full"] {
+        let s = run(&args[0], backtrace);
+        let inner = s.lines().find(|l| l.contains("backtrace_inlined::inner")).expect(&s);
+        let outer = s.lines().find(|l| l.contains("backtrace_inlined::outer")).expect(&s);
+        assert!(inner.contains(" (inlined)"), "not marked as inlined: {}", s);
+        assert!(!outer.contains(" (inlined)"), "bad output: {}", s);
+        // Like frames without inlined functions, the index goes on the first
+        // line of the frame.
+        let numbered = |line: &str| line.trim_start().chars().next().unwrap().is_digit(10);
+        assert!(numbered(inner), "bad output: {}", s);
+        assert!(!numbered(outer), "frame numbered twice: {}", s);
+    }
+}
@@ -0,0 +1,103 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-cloudabi spawning processes is not supported
+// ignore-emscripten spawning processes is not supported
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no processes
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g
+
+use std::env;
+use std::process::Command;
+use std::str;
+
+// Recurse through two functions so that the frames aren't collapsed as
+// repetitions of each other.
+#[inline(never)]
+fn ping(n: usize) {
+    if n == 0 {
+        panic!("bottom");
+    }
+    pong(n - 1);
+    // Prevent tail call optimization.
+    let _v = vec![n];
+}
+
+#[inline(never)]
+fn pong(n: usize) {
+    ping(n);
+    // Prevent tail call optimization.
+    let _v = vec![n];
+}
+
+fn run(me: &str, limit: &str) -> String {
+    run_quiet(me, limit, "0")
+}
+
+fn run_quiet(me: &str, limit: &str, quiet: &str) -> String {
+    let out = Command::new(me)
+        .arg("fail")
+        .env("RUST_BACKTRACE", "1")
+        .env("RUST_BACKTRACE_LIMIT", limit)
+        .env("RUST_BACKTRACE_QUIET", quiet)
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    str::from_utf8(&out.stderr).unwrap().to_string()
+}
+
+fn run_full(me: &str, limit: Option<&str>) -> String {
+    let mut cmd = Command::new(me);
+    cmd.arg("fail").env("RUST_BACKTRACE", "full");
+    if let Some(limit) = limit {
+        cmd.env("RUST_BACKTRACE_FULL_LIMIT", limit);
+    }
+    let out = cmd.output().unwrap();
+    assert!(!out.status.success());
+    str::from_utf8(&out.stderr).unwrap().to_string()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 && args[1] == "fail" {
+        ping(100);
+        return;
+    }
+
+    let s = run(&args[0], "2");
+    assert!(s.contains("   0: ") && s.contains("   1: "), "bad output: {}", s);
+    assert!(!s.contains("   2: "), "limit not honored: {}", s);
+    let note = "note: reached frame limit (2); increase `RUST_BACKTRACE_LIMIT` to print more.";
+    assert!(s.contains(note), "missing note: {}", s);
+    // The whole stack is still counted, ping and pong alone make up 200 frames.
+    let summary = s.lines().find(|l| l.starts_with("note: backtrace contained")).expect(&s);
+    assert!(summary.contains("(2 shown"), "bad summary: {}", s);
+    let total: usize = summary.split(' ').nth(3).unwrap().parse().unwrap();
+    assert!(total > 200, "bad summary: {}", s);
+
+    // Quiet backtraces are truncated the same way, just without the notes
+    let s = run_quiet(&args[0], "2", "1");
+    assert!(s.contains("   1: ") && !s.contains("   2: "), "limit not honored: {}", s);
+    assert!(!s.contains("note: reached frame limit"), "unexpected note: {}", s);
+    assert!(!s.contains("note: backtrace contained"), "unexpected summary: {}", s);
+
+    let s = run(&args[0], "0");
+    assert!(s.contains(" 150: "), "limit not lifted: {}", s);
+    // Nothing was left out but the runtime frames, so the frames aren't counted.
+    assert!(!s.contains("note: backtrace contained"), "unexpected summary: {}", s);
+    assert!(s.contains("note: Some details are omitted, run with `RUST_BACKTRACE=full`"), "{}", s);
+    assert!(!s.contains("note: reached frame limit"), "unexpected note: {}", s);
+
+    let s = run(&args[0], "bogus");
+    assert!(s.contains("  99: ") && !s.contains(" 100: "), "bad default: {}", s);
+
+    // Full backtraces have a separate, much higher limit.
+    let s = run_full(&args[0], None);
+    assert!(s.contains(" 150: "), "bad default: {}", s);
+    assert!(!s.contains("RUST_BACKTRACE_FULL_LIMIT"), "unexpected note: {}", s);
+
+    let s = run_full(&args[0], Some("3"));
+    assert!(s.contains("   2: ") && !s.contains("   3: "), "limit not honored: {}", s);
+    let note = "note: Frames past the first 3 are omitted, set `RUST_BACKTRACE_FULL_LIMIT`";
+    assert!(s.contains(note), "missing note: {}", s);
+}
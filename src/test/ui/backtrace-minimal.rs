@@ -0,0 +1,34 @@
+// run-pass
+// ignore-emscripten no backtrace support
+
+#![feature(backtrace)]
+
+use std::backtrace::{print_minimal, print_minimal_for_signal};
+use std::mem;
+use std::str;
+
+fn main() {
+    let mut out = Vec::new();
+    print_minimal(&mut out).unwrap();
+    let s = str::from_utf8(&out).unwrap();
+    for line in s.lines() {
+        assert!(line.starts_with("0x"), "bad output: {}", s);
+        assert_eq!(line.len(), 2 + 2 * mem::size_of::<usize>(), "bad output: {}", s);
+        assert!(usize::from_str_radix(&line[2..], 16).is_ok(), "bad output: {}", s);
+    }
+
+    let mut out = Vec::new();
+    print_minimal_for_signal(&mut out, 11).unwrap();
+    let s = str::from_utf8(&out).unwrap();
+    let header = s.lines().next().unwrap();
+    if cfg!(target_os = "linux") {
+        assert_eq!(header, "Process received signal SIGSEGV (11) - backtrace follows");
+    }
+    assert!(header.starts_with("Process received signal "), "bad output: {}", s);
+    assert!(s.lines().skip(1).all(|line| line.starts_with("0x")), "bad output: {}", s);
+
+    let mut out = Vec::new();
+    print_minimal_for_signal(&mut out, 1000).unwrap();
+    let s = str::from_utf8(&out).unwrap();
+    assert!(s.starts_with("Process received signal 1000 - backtrace follows\n"), "{}", s);
+}
@@ -0,0 +1,45 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-cloudabi spawning processes is not supported
+// ignore-emscripten spawning processes is not supported
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no processes
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g
+
+use std::env;
+use std::process::Command;
+use std::str;
+
+#[inline(never)]
+fn fail(v: Option<u8>) -> u8 {
+    v.unwrap()
+}
+
+fn run(me: &str, vars: &[(&str, &str)]) -> String {
+    let mut cmd = Command::new(me);
+    cmd.arg("fail").env("RUST_BACKTRACE", "1");
+    for &(k, v) in vars {
+        cmd.env(k, v);
+    }
+    let out = cmd.output().unwrap();
+    assert!(!out.status.success());
+    str::from_utf8(&out.stderr).unwrap().to_string()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 && args[1] == "fail" {
+        fail(None);
+        return;
+    }
+
+    let s = run(&args[0], &[]);
+    let origin = "backtrace_panic_origin::fail <- panic originated here\n";
+    assert!(s.contains(origin), "bad output: {}", s);
+    assert_eq!(s.matches("panic originated here").count(), 1, "bad output: {}", s);
+
+    let s = run(&args[0], &[("RUST_BACKTRACE_QUIET", "1")]);
+    assert!(s.contains("backtrace_panic_origin::fail"), "bad output: {}", s);
+    assert!(!s.contains("panic originated here"), "bad output: {}", s);
+}
@@ -0,0 +1,43 @@
+// run-pass
+// only-windows other platforms use forward slashes anyway
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::str;
+
+fn run(me: &str, posix: &str) -> String {
+    // Run from the directory of this file so its frames are printed with a
+    // path relative to it.
+    let dir = env::current_dir().unwrap().join(Path::new(file!()).parent().unwrap());
+    let out = Command::new(me)
+        .arg("fail")
+        .current_dir(dir)
+        .env("RUST_BACKTRACE", "1")
+        .env("RUST_BACKTRACE_POSIX_PATHS", posix)
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    str::from_utf8(&out.stderr).unwrap().to_string()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 && args[1] == "fail" {
+        panic!("posix paths");
+    }
+
+    let file = "backtrace-posix-paths.rs:";
+    let me = env::current_dir().unwrap().join(&args[0]);
+    let me = me.to_str().unwrap();
+    let s = run(me, "1");
+    let line = s.lines().find(|line| line.contains(file)).expect(&s);
+    assert!(line.trim_start().starts_with("at ./"), "bad output: {}", s);
+    assert!(!line.contains('\\'), "bad output: {}", s);
+
+    let s = run(me, "0");
+    let line = s.lines().find(|line| line.contains(file)).expect(&s);
+    assert!(line.trim_start().starts_with("at .\\"), "bad output: {}", s);
+}
@@ -0,0 +1,29 @@
+// run-pass
+// ignore-emscripten no backtrace support
+
+#![feature(backtrace)]
+
+use std::backtrace::print_into;
+use std::mem;
+use std::str;
+
+fn main() {
+    let line = 3 + 2 * mem::size_of::<usize>();
+
+    let mut buf = [0u8; 4096];
+    let len = print_into(&mut buf);
+    assert!(len > 0 && len % line == 0, "{} bytes", len);
+    let s = str::from_utf8(&buf[..len]).unwrap();
+    for l in s.lines() {
+        assert!(l.starts_with("0x"), "bad output: {}", s);
+        assert!(usize::from_str_radix(&l[2..], 16).is_ok(), "bad output: {}", s);
+    }
+
+    // Only whole lines are written if the buffer is too small.
+    let mut small = [0u8; 40];
+    let len = print_into(&mut small);
+    assert_eq!(len, 40 / line * line);
+    assert!(small[len..].iter().all(|&b| b == 0));
+
+    assert_eq!(print_into(&mut []), 0);
+}
@@ -0,0 +1,44 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-cloudabi spawning processes is not supported
+// ignore-emscripten spawning processes is not supported
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no processes
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g
+
+use std::env;
+use std::process::Command;
+use std::str;
+
+#[inline(never)]
+fn recurse(n: usize) {
+    if n == 0 {
+        panic!("bottom");
+    }
+    recurse(n - 1);
+    // Prevent tail call optimization.
+    let _v = vec![n];
+}
+
+fn run(me: &str, backtrace: &str) -> String {
+    let out = Command::new(me).arg("fail").env("RUST_BACKTRACE", backtrace).output().unwrap();
+    assert!(!out.status.success());
+    str::from_utf8(&out.stderr).unwrap().to_string()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 && args[1] == "fail" {
+        recurse(50);
+        return;
+    }
+
+    let s = run(&args[0], "1");
+    assert_eq!(s.matches("backtrace_recursion::recurse").count(), 2, "bad output: {}", s);
+    assert!(s.contains("(repeated 50 times)"), "not collapsed: {}", s);
+
+    let s = run(&args[0], "full");
+    assert!(s.matches("backtrace_recursion::recurse").count() > 50, "collapsed: {}", s);
+    assert!(!s.contains("repeated"), "collapsed: {}", s);
+}
@@ -0,0 +1,47 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-cloudabi spawning processes is not supported
+// ignore-emscripten spawning processes is not supported
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no processes
+// ignore-windows the home directory isn't read from `HOME`
+// compile-flags:-g
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+use std::str;
+
+fn run(me: &str, home: &Path, redact: &str) -> String {
+    let out = Command::new(me)
+        .arg("fail")
+        .env("RUST_BACKTRACE", "full")
+        .env("RUST_BACKTRACE_REDACT_HOME", redact)
+        .env("HOME", home)
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    str::from_utf8(&out.stderr).unwrap().to_string()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 && args[1] == "fail" {
+        panic!("redact");
+    }
+
+    // Pretend the home directory is the one this file is in.
+    let file = Path::new(file!());
+    if !file.is_absolute() {
+        return;
+    }
+    let home = file.parent().unwrap();
+    let home_str = home.to_str().unwrap();
+
+    let s = run(&args[0], home, "1");
+    assert!(s.contains("at ~/backtrace-redact-home.rs:"), "bad output: {}", s);
+    assert!(!s.contains(&format!("{}/backtrace-redact-home.rs", home_str)), "bad output: {}", s);
+
+    let s = run(&args[0], home, "0");
+    assert!(s.contains(&format!("{}/backtrace-redact-home.rs", home_str)), "bad output: {}", s);
+}
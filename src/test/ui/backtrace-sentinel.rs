@@ -0,0 +1,59 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-cloudabi spawning processes is not supported
+// ignore-emscripten spawning processes is not supported
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no processes
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g
+
+#![feature(backtrace)]
+
+use std::backtrace::add_short_backtrace_sentinel;
+use std::env;
+use std::ffi::c_void;
+use std::process::Command;
+use std::str;
+
+#[inline(never)]
+fn run_task(task: &dyn Fn()) {
+    task();
+    // Prevent tail call optimization.
+    let _v = vec![1];
+}
+
+#[inline(never)]
+fn executor_internals() {
+    run_task(&|| panic!("task failed"));
+    // Prevent tail call optimization.
+    let _v = vec![2];
+}
+
+fn run(me: &str, register: bool) -> String {
+    let mut cmd = Command::new(me);
+    cmd.arg(if register { "register" } else { "plain" }).env("RUST_BACKTRACE", "1");
+    let out = cmd.output().unwrap();
+    assert!(!out.status.success());
+    str::from_utf8(&out.stderr).unwrap().to_string()
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 {
+        if args[1] == "register" {
+            add_short_backtrace_sentinel(run_task as *const c_void);
+        }
+        executor_internals();
+        return;
+    }
+
+    let s = run(&args[0], false);
+    assert!(s.contains("backtrace_sentinel::run_task"), "bad output: {}", s);
+    assert!(s.contains("backtrace_sentinel::executor_internals"), "bad output: {}", s);
+
+    // The closure ran by the executor is still shown, the executor isn't.
+    let s = run(&args[0], true);
+    assert!(s.contains("backtrace_sentinel::executor_internals::{{closure}}"), "{}", s);
+    assert!(!s.contains("backtrace_sentinel::run_task"), "not trimmed: {}", s);
+    assert!(!s.contains("backtrace_sentinel::executor_internals\n"), "not trimmed: {}", s);
+}
@@ -0,0 +1,36 @@
+// run-pass
+// ignore-android FIXME #17520
+// ignore-cloudabi spawning processes is not supported
+// ignore-emscripten spawning processes is not supported
+// ignore-openbsd no support for libbacktrace without filename
+// ignore-sgx no processes
+// ignore-msvc see #62897 and `backtrace-debuginfo.rs` test
+// compile-flags:-g
+
+use std::env;
+use std::process::Command;
+use std::str;
+use std::thread;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() >= 2 && args[1] == "fail" {
+        let worker = thread::Builder::new().name("worker".to_string());
+        worker.spawn(|| panic!("fail")).unwrap().join().unwrap_err();
+        thread::spawn(|| panic!("fail")).join().unwrap_err();
+        return;
+    }
+
+    let out = Command::new(&args[0]).arg("fail").env("RUST_BACKTRACE", "1").output().unwrap();
+    let s = str::from_utf8(&out.stderr).unwrap();
+    let headers: Vec<&str> = s.lines().filter(|l| l.starts_with("backtrace (thread ")).collect();
+    assert_eq!(headers.len(), 2, "bad output: {}", s);
+    assert!(headers[0].starts_with("backtrace (thread \"worker\", id "), "bad output: {}", s);
+    assert!(!headers[1].contains('"'), "bad output: {}", s);
+    if cfg!(any(target_os = "linux", windows)) {
+        // The threads are told apart by the operating system as well.
+        let os_ids: Vec<&str> =
+            headers.iter().map(|h| h.split(", os id ").nth(1).unwrap()).collect();
+        assert!(os_ids[0] != os_ids[1], "bad output: {}", s);
+    }
+}
@@ -8,6 +8,7 @@
 // compile-flags:-g
 
 use std::env;
+use std::path::Path;
 use std::process::{Command, Stdio};
 use std::str;
 
@@ -15,10 +16,22 @@ use std::str;
 fn foo() {
     let _v = vec![1, 2, 3];
     if env::var_os("IS_TEST").is_some() {
-        panic!()
+        panic!() // this comment shows up in the backtrace
     }
 }
 
+#[inline(never)]
+fn outer() {
+    inner();
+    // Prevent tail call optimization.
+    let _v = vec![1];
+}
+
+#[inline(never)]
+fn inner() {
+    panic!("nested");
+}
+
 #[inline(never)]
 fn double() {
     struct Double;
@@ -40,6 +53,28 @@ fn template(me: &str) -> Command {
     return m;
 }
 
+fn output(mut cmd: Command) -> String {
+    let out = cmd.output().unwrap();
+    assert!(!out.status.success());
+    str::from_utf8(&out.stderr).unwrap().to_string()
+}
+
+// Runs this test with `arg` and the environment variables `vars`, returning
+// what it printed to stderr.
+fn run(me: &str, arg: &str, vars: &[(&str, &str)]) -> String {
+    let mut cmd = template(me);
+    cmd.arg(arg);
+    for &(key, val) in vars {
+        cmd.env(key, val);
+    }
+    output(cmd)
+}
+
+// Returns what follows the panic message in `s`.
+fn after_message(s: &str) -> &str {
+    &s[s.find('\n').unwrap_or(s.len())..]
+}
+
 fn expected(fn_name: &str) -> String {
     format!(" backtrace::{}", fn_name)
 }
@@ -69,6 +104,25 @@ fn runtest(me: &str) {
             "bad output: {}", s);
     assert!(s.contains(" 0:"), "the frame number should start at 0");
 
+    // `short` is an explicit alias of `1`
+    let p = template(me).arg("fail").env("RUST_BACKTRACE", "short").spawn().unwrap();
+    let out = p.wait_with_output().unwrap();
+    assert!(!out.status.success());
+    let s = str::from_utf8(&out.stderr).unwrap();
+    assert!(s.contains("stack backtrace") && s.contains(&expected("foo")),
+            "bad output short: {}", s);
+    assert!(!s.contains("unrecognized"), "bad output short: {}", s);
+
+    // Unknown values still print a short backtrace, but warn about it once
+    let p = template(me).arg("double-fail").env("RUST_BACKTRACE", "shrt").spawn().unwrap();
+    let out = p.wait_with_output().unwrap();
+    assert!(!out.status.success());
+    let s = str::from_utf8(&out.stderr).unwrap();
+    assert!(s.contains("stack backtrace") && s.contains(&expected("double")),
+            "bad output typo: {}", s);
+    assert_eq!(s.matches("unrecognized value \"shrt\" for RUST_BACKTRACE").count(), 1,
+               "bad output typo: {}", s);
+
     // Make sure the stack trace is *not* printed
     // (Remove RUST_BACKTRACE from our own environment, in case developer
     // is running `make check` with it on.)
@@ -113,13 +167,349 @@ fn runtest(me: &str) {
             "bad output4: {}", s);
 }
 
+// Pins down the layout of default short and full backtraces.
+fn default_output(me: &str) {
+    let hex_width = 2 + 2 * std::mem::size_of::<usize>();
+
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1")]);
+    let lines: Vec<&str> = s.lines().collect();
+    let start = lines.iter().position(|&l| l == "stack backtrace:").expect(&s);
+    let foo = lines.iter().position(|l| l.contains(": backtrace::foo")).expect(&s);
+    assert!(lines[foo].ends_with(": backtrace::foo <- panic originated here"), "{}", s);
+    assert!(lines[start + 1].starts_with("   0: "), "bad output: {}", s);
+    let index = lines[foo].split(':').next().unwrap();
+    assert_eq!(index.len(), 4, "bad output: {}", s);
+    assert!(index.trim_start().parse::<usize>().is_ok(), "bad output: {}", s);
+    let location = lines[foo + 1];
+    assert!(location.starts_with(&format!("{:13}at ", "")), "bad output: {}", s);
+    assert!(location.contains("ui/backtrace.rs:"), "bad output: {}", s);
+    assert!(!s.contains(" - "), "not short: {}", s);
+    assert!(!s.contains("lang_start"), "not trimmed: {}", s);
+    assert!(s.ends_with("note: Some details are omitted, run with `RUST_BACKTRACE=full` \
+                         for a verbose backtrace.\n"), "bad output: {}", s);
+
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "full")]);
+    let lines: Vec<&str> = s.lines().collect();
+    let foo = lines.iter().position(|l| l.contains(" - backtrace::foo::h")).expect(&s);
+    let (index, rest) = lines[foo].split_at(6);
+    assert!(index.ends_with(": ") && index[..4].trim_start().parse::<usize>().is_ok(),
+            "bad output: {}", s);
+    let (ip, rest) = rest.split_at(hex_width);
+    assert!(ip.trim_start().starts_with("0x"), "bad output: {}", s);
+    assert!(usize::from_str_radix(&ip.trim_start()[2..], 16).is_ok(), "bad output: {}", s);
+    assert_eq!(rest.len(), " - backtrace::foo::h".len() + 16, "bad output: {}", s);
+    let location = lines[foo + 1];
+    assert!(location.starts_with(&format!("{:1$}at ", "", hex_width + 13)), "bad output: {}", s);
+    assert!(s.contains("lang_start"), "trimmed: {}", s);
+    assert!(!s.contains("note: Some details are omitted"), "bad output: {}", s);
+    assert!(!s.contains("this comment shows up") && !s.contains("ip=0x"), "bad output: {}", s);
+}
+
+// Checks the formats picked through `RUST_BACKTRACE` and
+// `RUST_BACKTRACE_FORMAT`.
+fn formats(me: &str) {
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1"), ("RUST_BACKTRACE_FORMAT", "json")]);
+    let json = &s[s.find('[').expect(&s)..];
+    assert!(json.trim_end().ends_with(']'), "bad output: {}", s);
+    assert!(json.contains("{\"index\":0,\"symbol_name\":"), "bad output: {}", s);
+    assert!(json.contains("backtrace::foo"), "bad output: {}", s);
+    assert!(!s.contains("stack backtrace:"), "text output mixed in: {}", s);
+
+    let s = run(me, "nested-fail", &[("RUST_BACKTRACE", "compact")]);
+    let line = s.lines().find(|l| l.contains(" <- ")).expect(&s);
+    assert!(line.contains("backtrace::main <- backtrace::outer <- backtrace::inner <- "),
+            "bad output: {}", s);
+    assert!(!line.contains("lang_start"), "not trimmed: {}", s);
+    assert!(!s.contains("stack backtrace:"), "not compact: {}", s);
+    assert!(!after_message(&s).contains(" at "), "not compact: {}", s);
+
+    // Everything after the panic message is an address, and the runtime's
+    // frames after `__rust_begin_short_backtrace` are trimmed.
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "addrs")]);
+    assert!(!s.contains(&expected("foo")), "resolved: {}", s);
+    let addrs = s.lines().skip(1).count();
+    for line in s.lines().skip(1) {
+        assert!(line.starts_with("0x"), "bad output: {}", s);
+        assert!(usize::from_str_radix(&line[2..], 16).is_ok(), "bad output: {}", s);
+    }
+    assert!(addrs > 0, "bad output: {}", s);
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "full")]);
+    let full = s.lines().filter(|line| line.contains(": 0x")).count();
+    assert!(addrs < full, "not trimmed: {} of {} frames", addrs, full);
+
+    // Frames are printed by symbol and offset, even though there is debug
+    // info to print their locations.
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "symbols")]);
+    let line = s.lines().find(|l| l.contains(&expected("foo"))).expect(&s);
+    assert!(line.contains("+0x"), "no offset: {}", s);
+    assert!(!after_message(&s).contains(" at "), "location printed: {}", s);
+    assert!(!after_message(&s).contains("ui/backtrace.rs"), "path printed: {}", s);
+
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "full")]);
+    assert!(s.contains(" std::panicking::"), "no std frames: {}", s);
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "full-no-std")]);
+    assert!(s.contains(&expected("foo")), "bad output: {}", s);
+    assert!(s.contains(" - backtrace::main"), "not a full backtrace: {}", s);
+    assert!(!s.contains(" std::panicking::"), "std frames not hidden: {}", s);
+    assert!(!s.contains(" core::"), "core frames not hidden: {}", s);
+    assert!(s.contains("frames hidden ..."), "hidden frames not reported: {}", s);
+    assert!(!s.contains("unrecognized"), "bad output: {}", s);
+
+    // Full backtraces group frames by crate and offset them into their
+    // symbol, resolved frames aren't located through their module.
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "full")]);
+    let foo = s.find(" - backtrace::foo").expect(&s);
+    let ours = s[..foo].rfind("--- in crate `").expect(&s);
+    assert!(s[ours..].starts_with("--- in crate `backtrace` ---"), "bad group: {}", s);
+    assert!(s.contains("--- in crate `std` ---"), "no std group: {}", s);
+    let line = s.lines().find(|l| l.contains(" - backtrace::foo")).unwrap();
+    assert!(line.contains("+0x"), "no offset: {}", s);
+    if cfg!(target_os = "linux") {
+        let exe = env::current_exe().unwrap();
+        let module = format!("({}+0x", exe.file_name().unwrap().to_str().unwrap());
+        assert!(!line.contains(&module[..]), "unexpected module offset: {}", s);
+    }
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1")]);
+    assert!(!s.contains("--- in crate"), "grouped short backtrace: {}", s);
+}
+
+// Checks the settings which pick the frames printed.
+fn frame_selection(me: &str) {
+    // The whole stack is printed, still laid out like a short backtrace.
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1"), ("RUST_BACKTRACE_NO_TRIM", "1")]);
+    assert!(s.contains("lang_start"), "trimmed: {}", s);
+    assert!(s.contains("__rust_begin_short_backtrace"), "trimmed: {}", s);
+    assert!(!s.contains(": 0x"), "not short: {}", s);
+
+    let foo_index = |s: &str| -> Option<usize> {
+        let line = s.lines().find(|l| l.contains(&expected("foo")))?;
+        line.split(':').next()?.trim().parse().ok()
+    };
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1"), ("RUST_BACKTRACE_SKIP", "0")]);
+    let idx = foo_index(&s).expect(&s);
+    assert!(idx > 0, "bad output: {}", s);
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1"), ("RUST_BACKTRACE_SKIP", "1")]);
+    assert_eq!(foo_index(&s), Some(idx - 1), "bad output: {}", s);
+    assert!(s.contains("   0: "), "the frame number should start at 0: {}", s);
+    let skip = (idx + 1).to_string();
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1"), ("RUST_BACKTRACE_SKIP", &skip[..])]);
+    assert_eq!(foo_index(&s), None, "bad output: {}", s);
+
+    for &backtrace in &["1", "full"] {
+        let vars = [("RUST_BACKTRACE", backtrace), ("RUST_BACKTRACE_ONLY", "backtrace::")];
+        let s = run(me, "fail", &vars);
+        assert!(s.contains(&expected("foo")), "bad output: {}", s);
+        assert!(s.contains(&expected("main")), "bad output: {}", s);
+        assert!(!s.contains("std::panicking"), "not filtered: {}", s);
+        assert!(s.contains(" frames hidden ..."), "bad output: {}", s);
+    }
+    let vars = [("RUST_BACKTRACE", "1"), ("RUST_BACKTRACE_ONLY", "backtrace::main, std::")];
+    let s = run(me, "fail", &vars);
+    assert!(!s.contains(&expected("foo")), "not filtered: {}", s);
+    assert!(s.contains(&expected("main")), "bad output: {}", s);
+    assert!(s.contains("std::panicking"), "bad output: {}", s);
+
+    let filter = ("RUST_BACKTRACE_FILTER", "backtrace::inner, backtrace::outer, nonexistent");
+    let s = run(me, "nested-fail", &[("RUST_BACKTRACE", "1"), filter]);
+    assert!(!s.contains(&expected("inner")) && !s.contains(&expected("outer")),
+            "not filtered: {}", s);
+    assert!(s.contains("frames hidden ..."), "missing marker: {}", s);
+    assert!(s.contains(&expected("main")), "bad output: {}", s);
+    let s = run(me, "nested-fail", &[("RUST_BACKTRACE", "full"), filter]);
+    assert!(s.contains(&expected("inner")), "full backtrace filtered: {}", s);
+    let filter_full = ("RUST_BACKTRACE_FILTER_FULL", "1");
+    let s = run(me, "nested-fail", &[("RUST_BACKTRACE", "full"), filter, filter_full]);
+    assert!(!s.contains(&expected("inner")), "not filtered: {}", s);
+
+    let s = run(me, "nested-fail", &[("RUST_BACKTRACE", "1")]);
+    let outer = s.find(&expected("outer")).expect(&s);
+    let inner = s.find(&expected("inner")).expect(&s);
+    assert!(inner < outer, "not innermost first: {}", s);
+    let s = run(me, "nested-fail", &[("RUST_BACKTRACE", "1"), ("RUST_BACKTRACE_REVERSE", "1")]);
+    let outer = s.find(&expected("outer")).expect(&s);
+    let inner = s.find(&expected("inner")).expect(&s);
+    assert!(outer < inner, "not innermost last: {}", s);
+    let first = s.find("   0: ").expect(&s);
+    let second = s.find("   1: ").expect(&s);
+    assert!(second < first, "indices not kept: {}", s);
+
+    // Without any budget no frame is resolved.
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1"), ("RUST_BACKTRACE_TIMEOUT_MS", "0")]);
+    assert!(!s.contains(&expected("foo")), "bad output: {}", s);
+    assert!(s.contains("   0: 0x"), "bad output: {}", s);
+    assert!(s.contains("<unresolved>"), "bad output: {}", s);
+    assert!(s.contains("weren't resolved as `RUST_BACKTRACE_TIMEOUT_MS` was exceeded"), "{}", s);
+    let timeout = ("RUST_BACKTRACE_TIMEOUT_MS", "60000");
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1"), timeout]);
+    assert!(s.contains(&expected("foo")), "bad output: {}", s);
+    assert!(!s.contains("<unresolved>"), "bad output: {}", s);
+}
+
+// Checks the settings which change how the frames are printed, each of them
+// off unless set to `1`.
+fn decorations(me: &str) {
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1")]);
+    for &unset in &[
+        "after the process started",
+        "frames had source locations",
+        "BACKTRACE (",
+        "ip=0x",
+        "this comment shows up",
+        "<unresolved>",
+        "\0",
+    ] {
+        assert!(!s.contains(unset), "bad output: {}", s);
+    }
+    assert!(!s.lines().any(|l| l.starts_with('>')), "marked by default: {}", s);
+
+    let prefix = ("RUST_BACKTRACE_LINE_PREFIX", "[app] ");
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1"), prefix]);
+    let start = s.find("[app] stack backtrace:").expect(&s);
+    assert!(s[start..].lines().all(|line| line.starts_with("[app] ")), "bad output: {}", s);
+    assert!(s.contains("[app] note: "), "bad output: {}", s);
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1"), ("RUST_BACKTRACE_LINE_PREFIX", "")]);
+    assert!(s.lines().any(|line| line == "stack backtrace:"), "bad output: {}", s);
+
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1"), ("RUST_BACKTRACE_UPTIME", "1")]);
+    let line = s.lines().find(|l| l.starts_with("backtrace taken "));
+    assert!(line.map_or(false, |l| l.ends_with("s after the process started")),
+            "bad output: {}", s);
+    assert!(s.find("backtrace taken ") < s.find(&expected("foo")), "bad output: {}", s);
+
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1"), ("RUST_BACKTRACE_VERBOSE", "1")]);
+    let line = s.lines().find(|l| l.ends_with(" frames had source locations")).expect(&s);
+    let counts = line["note: ".len()..].split(' ').next().unwrap();
+    let mut counts = counts.split('/').map(|n| n.parse::<usize>().unwrap());
+    let (located, shown) = (counts.next().unwrap(), counts.next().unwrap());
+    assert!(located > 0 && located <= shown, "bad output: {}", s);
+
+    let child = template(me)
+        .arg("fail")
+        .env("RUST_BACKTRACE", "1")
+        .env("RUST_BACKTRACE_MARKERS", "1")
+        .spawn()
+        .unwrap();
+    let id = child.id();
+    let out = child.wait_with_output().unwrap();
+    assert!(!out.status.success());
+    let s = str::from_utf8(&out.stderr).unwrap();
+    let begin = s.find(&format!("===== BEGIN BACKTRACE (process {}, thread ", id));
+    let end = s.find(&format!("===== END BACKTRACE (process {}, thread ", id));
+    let frame = s.find(&expected("foo"));
+    assert!(begin.is_some() && end.is_some() && frame.is_some(), "bad output: {}", s);
+    assert!(begin < frame && frame < end, "bad output: {}", s);
+    // The notes following the frames belong to the backtrace as well.
+    assert!(s.find("note: Some details are omitted") < end, "bad output: {}", s);
+
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1"), ("RUST_BACKTRACE_DELIM", "nul")]);
+    let start = s.find("stack backtrace:\n").expect(&s) + "stack backtrace:\n".len();
+    let frames: Vec<&str> = s[start..].split('\0').collect();
+    assert!(frames.len() > 2, "bad output: {:?}", s);
+    // Every frame starts with its index, whatever lines follow it.
+    for (i, frame) in frames[..frames.len() - 1].iter().enumerate() {
+        assert!(frame.starts_with(&format!("{:4}: ", i)), "bad frame {}: {:?}", i, s);
+        assert!(frame.ends_with('\n'), "bad frame {}: {:?}", i, s);
+    }
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1"), ("RUST_BACKTRACE_DELIM", "---\n")]);
+    assert!(s.contains("\n---\n   1: "), "bad output: {}", s);
+
+    // Every frame is followed by its instruction pointer, after its location.
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "full"), ("RUST_BACKTRACE_REGS", "1")]);
+    let lines: Vec<&str> = s.lines().collect();
+    let mut frames = 0;
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(pos) = line.find(": 0x") {
+            frames += 1;
+            let ip = line[pos + 2..].split(' ').next().unwrap();
+            let regs = lines[i + 1..].iter().find(|l| l.trim_start().starts_with("ip="));
+            assert_eq!(regs.map(|l| l.trim_start()), Some(&format!("ip={}", ip)[..]), "{}", s);
+        }
+    }
+    assert!(frames > 0, "bad output: {}", s);
+    assert_eq!(s.matches(" ip=0x").count(), frames, "bad output: {}", s);
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1"), ("RUST_BACKTRACE_REGS", "1")]);
+    assert!(!s.contains("ip=0x"), "bad output: {}", s);
+
+    let snippet = "// this comment shows up in the backtrace";
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "full"), ("RUST_BACKTRACE_SOURCE", "1")]);
+    assert!(s.contains(snippet), "no source: {}", s);
+    let s = run(me, "fail", &[("RUST_BACKTRACE", "1"), ("RUST_BACKTRACE_SOURCE", "1")]);
+    assert!(!s.contains(snippet), "unexpected source: {}", s);
+
+    // The frames of this test count as the user's own code when running from
+    // the directory it's in.
+    let exe = env::current_exe().unwrap();
+    let mut cmd = template(exe.to_str().unwrap());
+    cmd.arg("fail")
+        .current_dir(Path::new(file!()).parent().unwrap())
+        .env("RUST_BACKTRACE", "1")
+        .env("RUST_BACKTRACE_USER_FRAMES", "1");
+    let s = output(cmd);
+    let line = s.lines().find(|l| l.contains(&expected("foo"))).expect(&s);
+    assert!(line.starts_with('>'), "not marked: {}", s);
+    let line = s.lines().find(|l| l.contains("std::panicking")).expect(&s);
+    assert!(line.starts_with(' '), "std frame marked: {}", s);
+
+    // Output which isn't a terminal is only colored if forced, `NO_COLOR`
+    // overrides `RUST_BACKTRACE_COLOR` and `CLICOLOR_FORCE` overrides both.
+    let color = |choice: &str, vars: &[(&str, &str)]| {
+        let mut cmd = template(me);
+        cmd.arg("fail")
+            .env("RUST_BACKTRACE", "1")
+            .env("RUST_BACKTRACE_COLOR", choice)
+            .env_remove("NO_COLOR")
+            .env_remove("CLICOLOR_FORCE");
+        for &(key, val) in vars {
+            cmd.env(key, val);
+        }
+        output(cmd)
+    };
+    let s = color("always", &[]);
+    assert!(s.contains("\x1b[1m") && s.contains("\x1b[0m"), "no color: {}", s);
+    for &choice in &["never", "auto"] {
+        let s = color(choice, &[]);
+        assert!(s.contains("stack backtrace:"), "bad output: {}", s);
+        assert!(!s.contains('\x1b'), "unexpected color: {}", s);
+    }
+    let s = color("always", &[("NO_COLOR", "")]);
+    assert!(!s.contains('\x1b'), "unexpected color: {}", s);
+    let s = color("never", &[("NO_COLOR", "1"), ("CLICOLOR_FORCE", "1")]);
+    assert!(s.contains("\x1b[1m"), "no color: {}", s);
+    let s = color("auto", &[("CLICOLOR_FORCE", "")]);
+    assert!(!s.contains('\x1b'), "unexpected color: {}", s);
+
+    // This binary uses the legacy mangling scheme.
+    let mangled = "_ZN9backtrace3foo17h";
+    for &backtrace in &["1", "full"] {
+        let vars = [("RUST_BACKTRACE", backtrace), ("RUST_BACKTRACE_MANGLED", "1")];
+        let s = run(me, "fail", &vars);
+        assert!(s.contains(mangled), "bad output: {}", s);
+        assert!(!s.contains(&expected("foo")), "bad output: {}", s);
+        assert!(after_message(&s).contains("ui/backtrace.rs:"), "no location: {}", s);
+
+        let styles = [("none", false), ("only-v0", false), ("only-legacy", true)];
+        for &(demangle, demangled) in &styles {
+            let vars = [("RUST_BACKTRACE", backtrace), ("RUST_BACKTRACE_DEMANGLE", demangle)];
+            let s = run(me, "fail", &vars);
+            assert_eq!(s.contains(&expected("foo")), demangled, "bad output: {}", s);
+            assert_eq!(s.contains(mangled), !demangled, "bad output: {}", s);
+        }
+    }
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     if args.len() >= 2 && args[1] == "fail" {
         foo();
     } else if args.len() >= 2 && args[1] == "double-fail" {
         double();
+    } else if args.len() >= 2 && args[1] == "nested-fail" {
+        outer();
     } else {
         runtest(&args[0]);
+        default_output(&args[0]);
+        formats(&args[0]);
+        frame_selection(&args[0]);
+        decorations(&args[0]);
     }
 }
@@ -33,14 +33,15 @@ fn runtest(me: &str) {
     assert!(p.status.success());
     assert!(String::from_utf8_lossy(&p.stdout).contains("stack backtrace:\n"));
     assert!(String::from_utf8_lossy(&p.stdout).contains("backtrace::main"));
+    assert!(!String::from_utf8_lossy(&p.stdout).contains("lang_start"));
 
     let p = Command::new(me).arg("a").env("RUST_BACKTRACE", "0").output().unwrap();
     assert!(p.status.success());
-    assert!(String::from_utf8_lossy(&p.stdout).contains("disabled backtrace\n"));
+    assert!(String::from_utf8_lossy(&p.stdout).contains("<backtrace disabled>\n"));
 
     let p = Command::new(me).arg("a").output().unwrap();
     assert!(p.status.success());
-    assert!(String::from_utf8_lossy(&p.stdout).contains("disabled backtrace\n"));
+    assert!(String::from_utf8_lossy(&p.stdout).contains("<backtrace disabled>\n"));
 
     let p = Command::new(me)
         .arg("a")
@@ -58,7 +59,7 @@ fn runtest(me: &str) {
         .output()
         .unwrap();
     assert!(p.status.success());
-    assert!(String::from_utf8_lossy(&p.stdout).contains("disabled backtrace\n"));
+    assert!(String::from_utf8_lossy(&p.stdout).contains("<backtrace disabled>\n"));
 
     let p = Command::new(me)
         .arg("force")